@@ -15,6 +15,7 @@ pub enum Op {
     // Logical
     And,
     Or,
+    Coalesce, // ??
     // Unary
     Not,
 }
@@ -22,14 +23,97 @@ pub enum Op {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Number(i64),
+    Float(f64),
+    String(String),
     Boolean(bool),
     Nil,
+    Array(Vec<Expr>),
+    /// A map literal's entries, as written.
+    Map(Vec<(Expr, Expr)>),
     Variable(String),
+    /// A local variable read, located by the resolver: `depth` scopes out from
+    /// the innermost scope, at index `slot` within that scope.
+    ResolvedVariable {
+        name: String,
+        depth: usize,
+        slot: usize,
+    },
     Binary(Box<Expr>, Op, Box<Expr>),
+    /// Chained comparisons such as `a < b <= c`: each operand is compared
+    /// with the one before it, and every operand is evaluated at most once.
+    Chain(Box<Expr>, Vec<(Op, Expr)>),
     Unary(Op, Box<Expr>),
     Call(String, Vec<Expr>),
+    /// `collection[index]`: an array element or a map value.
+    Index(Box<Expr>, Box<Expr>),
+    /// A call whose callee is itself an expression, such as `handlers[0](x)`.
+    /// Calls by name stay `Call`, which can also reach the built-ins.
+    CallValue(Box<Expr>, Vec<Expr>),
+    /// An expression written in parentheses. The parser keeps it so the
+    /// source can be printed back faithfully; the optimizer removes it.
+    Grouping(Box<Expr>),
+}
+
+/// A function parameter. Parameters with a default may be omitted by callers,
+/// and a trailing `rest` parameter collects any extra arguments into an array.
+/// `ty` is the optional annotation in `name: type`, checked on each call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub default: Option<Expr>,
+    pub rest: bool,
+    pub ty: Option<TypeTag>,
+}
+
+/// A type named in a parameter or return annotation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TypeTag {
+    Integer,
+    Float,
+    /// An integer or a float.
+    Number,
+    String,
+    Bool,
+    Nil,
+    Array,
+    Map,
+    Function,
+}
+
+impl TypeTag {
+    /// Looks up a type by the name written in an annotation. `int` is short
+    /// for `integer`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "int" | "integer" => TypeTag::Integer,
+            "float" => TypeTag::Float,
+            "number" => TypeTag::Number,
+            "string" => TypeTag::String,
+            "bool" => TypeTag::Bool,
+            "nil" => TypeTag::Nil,
+            "array" => TypeTag::Array,
+            "map" => TypeTag::Map,
+            "function" => TypeTag::Function,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TypeTag::Integer => "integer",
+            TypeTag::Float => "float",
+            TypeTag::Number => "number",
+            TypeTag::String => "string",
+            TypeTag::Bool => "bool",
+            TypeTag::Nil => "nil",
+            TypeTag::Array => "array",
+            TypeTag::Map => "map",
+            TypeTag::Function => "function",
+        }
+    }
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum Stmt {
     Let {
@@ -37,6 +121,11 @@ pub enum Stmt {
         mutable: bool,
         value: Expr,
     },
+    LetMulti {
+        names: Vec<String>,
+        mutable: bool,
+        value: Expr,
+    },
     Assign {
         name: String,
         value: Expr,
@@ -50,17 +139,41 @@ pub enum Stmt {
     While {
         condition: Expr,
         body: Vec<Stmt>,
+        /// Runs once the condition turns false, but not after a `break`.
+        else_branch: Option<Vec<Stmt>>,
     },
     Loop {
         body: Vec<Stmt>,
     },
+    Repeat {
+        count: Expr,
+        body: Vec<Stmt>,
+    },
+    /// `repeat do ... until condition`: runs the body, then stops once the
+    /// condition is true. The condition is outside the body's scope.
+    DoWhile {
+        body: Vec<Stmt>,
+        condition: Expr,
+    },
+    /// A C-style loop. `init` runs once in a scope of its own that encloses
+    /// the whole loop, so a variable it declares is gone once the loop ends.
+    /// `init` and `step` are comma-separated lists, run in order.
+    For {
+        init: Vec<Stmt>,
+        condition: Expr,
+        step: Vec<Stmt>,
+        body: Vec<Stmt>,
+    },
     Break,
     Continue,
     Return(Expr),
     Fn {
         name: String,
-        params: Vec<String>,
+        params: Vec<Param>,
+        /// The annotation in `fn f(): type do`, checked on each return.
+        return_type: Option<TypeTag>,
         body: Vec<Stmt>,
     },
     ExprStmt(Expr),
+    Import(String),
 }
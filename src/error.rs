@@ -0,0 +1,119 @@
+use std::fmt;
+
+/// An error from compiling or running a Blood program.
+///
+/// Host code can match on the variant; `Display` renders the same messages
+/// the command line prints.
+///
+/// Only `Parse` carries a line number. Statements and expressions are not
+/// tagged with source positions once parsed, so a runtime error can name the
+/// functions it unwound through (see `InFunction`) but not the line it came
+/// from. Giving runtime errors a line would mean adding a position to every
+/// `Stmt` and `Expr` node and to everything that builds or matches them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BloodError {
+    /// A lexer or parser error on the given 1-based line.
+    Parse {
+        line: usize,
+        message: String,
+    },
+    /// A variable or function name that is not defined.
+    UndefinedVariable(String),
+    /// An operator or condition given a value of the wrong type.
+    TypeMismatch(String),
+    DivideByZero,
+    ModuloByZero,
+    /// Integer arithmetic whose result doesn't fit in 64 bits.
+    IntegerOverflow,
+    /// An array read or `remove` at a position outside the array.
+    IndexOutOfBounds {
+        index: i64,
+        len: usize,
+    },
+    /// A call through something that isn't a function. Holds the callee as
+    /// written in the source, or the value when there is no source form.
+    NotAFunction(String),
+    /// A user function called with the wrong number of arguments. `expected`
+    /// describes the accepted count, e.g. `"2"` or `"at least 1"`.
+    Arity {
+        expected: String,
+        got: usize,
+    },
+    /// Any other runtime error, with its full message.
+    Runtime(String),
+    /// The script called `exit(code)`. This is not a failure: the script
+    /// asked to stop, and the host decides what to do with the code. It is
+    /// never wrapped in `InFunction`.
+    Exit(i32),
+    /// An error that unwound through user functions, named innermost first.
+    InFunction {
+        error: Box<BloodError>,
+        calls: Vec<String>,
+    },
+}
+
+impl BloodError {
+    /// Records that the error unwound out of the function `name`.
+    pub(crate) fn in_function(self, name: &str) -> Self {
+        match self {
+            BloodError::InFunction { error, mut calls } => {
+                calls.push(name.to_string());
+                BloodError::InFunction { error, calls }
+            }
+            BloodError::Exit(code) => BloodError::Exit(code),
+            error => BloodError::InFunction {
+                error: Box::new(error),
+                calls: vec![name.to_string()],
+            },
+        }
+    }
+}
+
+impl fmt::Display for BloodError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BloodError::Parse { line, message } => {
+                write!(f, "Syntax Error on line {}: {}", line, message)
+            }
+            BloodError::UndefinedVariable(name) => {
+                write!(f, "Runtime Error: Variable '{}' not defined.", name)
+            }
+            BloodError::TypeMismatch(message) => write!(f, "Runtime Error: {}", message),
+            BloodError::DivideByZero => write!(f, "Runtime Error: Division by zero."),
+            BloodError::ModuloByZero => write!(f, "Runtime Error: Modulo by zero."),
+            BloodError::IntegerOverflow => write!(f, "Runtime Error: integer overflow"),
+            BloodError::IndexOutOfBounds { index, len } => write!(
+                f,
+                "Runtime Error: Index {} is out of bounds for an array of length {}.",
+                index, len
+            ),
+            BloodError::NotAFunction(callee) => {
+                write!(f, "Runtime Error: '{}' is not a function.", callee)
+            }
+            BloodError::Arity { expected, got } => write!(
+                f,
+                "Runtime error: expected {} argument{}, got {}",
+                expected,
+                if expected == "1" { "" } else { "s" },
+                got
+            ),
+            BloodError::Runtime(message) => write!(f, "{}", message),
+            BloodError::Exit(code) => write!(f, "exit({})", code),
+            BloodError::InFunction { error, calls } => {
+                write!(f, "{}", error)?;
+                for name in calls {
+                    write!(f, "\n  in fn {}", name)?;
+                }
+                write!(f, "\n  at top level")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BloodError {}
+
+impl From<String> for BloodError {
+    fn from(message: String) -> Self {
+        BloodError::Runtime(message)
+    }
+}
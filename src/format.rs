@@ -0,0 +1,349 @@
+use crate::ast::{Expr, Op, Param, Stmt, TypeTag};
+
+/// Prints a parsed program back as source in the canonical layout: four
+/// spaces of indentation per block, one statement per line, single spaces
+/// around operators, and a blank line around each top-level function.
+///
+/// Parentheses stay where the source had them. Anything the parser doesn't
+/// keep comes back in its canonical form: comments are lost, `x++` prints as
+/// `x = x + 1`, number literals print in decimal, `int` prints as `integer`
+/// and character literals print as strings.
+pub fn format_program(stmts: &[Stmt]) -> String {
+    let mut formatter = Formatter {
+        out: String::new(),
+        indent: 0,
+    };
+    for (i, stmt) in stmts.iter().enumerate() {
+        if i > 0 && (is_fn(stmt) || is_fn(&stmts[i - 1])) {
+            formatter.out.push('\n');
+        }
+        formatter.stmt(stmt, i + 1 == stmts.len());
+    }
+    formatter.out
+}
+
+fn is_fn(stmt: &Stmt) -> bool {
+    matches!(stmt, Stmt::Fn { .. })
+}
+
+struct Formatter {
+    out: String,
+    // Current block depth.
+    indent: usize,
+}
+
+impl Formatter {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) {
+        self.indent += 1;
+        for (i, stmt) in stmts.iter().enumerate() {
+            self.stmt(stmt, i + 1 == stmts.len());
+        }
+        self.indent -= 1;
+    }
+
+    /// `last` is set for the final statement of a block, the only place a
+    /// bare `return` can't be read as returning the next line.
+    fn stmt(&mut self, stmt: &Stmt, last: bool) {
+        match stmt {
+            Stmt::If { .. } => {
+                self.if_chain(stmt, "if");
+                self.line("end");
+            }
+            Stmt::While {
+                condition,
+                body,
+                else_branch,
+            } => {
+                self.line(&format!("while {} do", expr(condition)));
+                self.block(body);
+                if let Some(else_branch) = else_branch {
+                    self.line("else");
+                    self.block(else_branch);
+                }
+                self.line("end");
+            }
+            Stmt::Loop { body } => {
+                self.line("loop do");
+                self.block(body);
+                self.line("end");
+            }
+            Stmt::Repeat { count, body } => {
+                self.line(&format!("repeat {} times do", expr(count)));
+                self.block(body);
+                self.line("end");
+            }
+            Stmt::DoWhile { body, condition } => {
+                self.line("repeat do");
+                self.block(body);
+                self.line(&format!("until {}", expr(condition)));
+            }
+            Stmt::For {
+                init,
+                condition,
+                step,
+                body,
+            } => {
+                self.line(&format!(
+                    "for {}; {}; {} do",
+                    simple_stmts(init),
+                    expr(condition),
+                    simple_stmts(step)
+                ));
+                self.block(body);
+                self.line("end");
+            }
+            Stmt::Fn {
+                name,
+                params,
+                return_type,
+                body,
+            } => {
+                let params: Vec<String> = params.iter().map(param).collect();
+                self.line(&format!(
+                    "fn {}({}){} do",
+                    name,
+                    params.join(", "),
+                    annotation(*return_type)
+                ));
+                self.block(body);
+                self.line("end");
+            }
+            _ => self.line(&simple_stmt(stmt, last)),
+        }
+    }
+
+    /// Prints an `if` and its branches without the closing `end`. An else
+    /// branch holding nothing but another `if` continues as `elseif`.
+    fn if_chain(&mut self, stmt: &Stmt, keyword: &str) {
+        let Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } = stmt
+        else {
+            unreachable!("if_chain called on a non-if statement");
+        };
+        self.line(&format!("{} {} then", keyword, expr(condition)));
+        self.block(then_branch);
+        match else_branch.as_deref() {
+            Some([nested @ Stmt::If { .. }]) => self.if_chain(nested, "elseif"),
+            Some(else_branch) => {
+                self.line("else");
+                self.block(else_branch);
+            }
+            None => {}
+        }
+    }
+}
+
+/// A statement that fits on one line.
+fn simple_stmt(stmt: &Stmt, last: bool) -> String {
+    match stmt {
+        Stmt::Let {
+            name,
+            mutable,
+            value,
+        } => format!("let {}{} = {}", let_mod(*mutable), name, expr(value)),
+        Stmt::LetMulti {
+            names,
+            mutable,
+            value,
+        } => format!(
+            "let {}{} = {}",
+            let_mod(*mutable),
+            names.join(", "),
+            expr(value)
+        ),
+        Stmt::Assign { name, value } => format!("{} = {}", name, expr(value)),
+        Stmt::Print(value) => format!("print({})", expr(value)),
+        Stmt::Break => "break".to_string(),
+        Stmt::Continue => "continue".to_string(),
+        Stmt::Return(Expr::Nil) if last => "return".to_string(),
+        Stmt::Return(value) => format!("return {}", expr(value)),
+        Stmt::ExprStmt(value) => expr(value),
+        Stmt::Import(path) => format!("import {}", string_literal(path)),
+        _ => unreachable!("block statement formatted on one line"),
+    }
+}
+
+/// The statements of a `for` clause, separated by commas.
+fn simple_stmts(stmts: &[Stmt]) -> String {
+    let stmts: Vec<String> = stmts.iter().map(|s| simple_stmt(s, false)).collect();
+    stmts.join(", ")
+}
+
+fn let_mod(mutable: bool) -> &'static str {
+    if mutable { "mod " } else { "" }
+}
+
+fn param(param: &Param) -> String {
+    if param.rest {
+        return format!("{}...", param.name);
+    }
+    let name = format!("{}{}", param.name, annotation(param.ty));
+    match &param.default {
+        Some(default) => format!("{} = {}", name, expr(default)),
+        None => name,
+    }
+}
+
+fn annotation(ty: Option<TypeTag>) -> String {
+    match ty {
+        Some(ty) => format!(": {}", ty.name()),
+        None => String::new(),
+    }
+}
+
+pub(crate) fn expr(e: &Expr) -> String {
+    match e {
+        Expr::Number(value) => value.to_string(),
+        Expr::Float(value) => format!("{:?}", value),
+        Expr::String(text) => string_literal(text),
+        Expr::Boolean(value) => value.to_string(),
+        Expr::Nil => "nil".to_string(),
+        Expr::Array(elements) => format!("[{}]", list(elements)),
+        Expr::Map(entries) => {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(key, value)| format!("{}: {}", expr(key), expr(value)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Expr::Variable(name) | Expr::ResolvedVariable { name, .. } => name.clone(),
+        Expr::Binary(left, op, right) => {
+            format!("{} {} {}", expr(left), op_text(op), expr(right))
+        }
+        Expr::Chain(first, rest) => {
+            let mut text = expr(first);
+            for (op, operand) in rest {
+                text.push_str(&format!(" {} {}", op_text(op), expr(operand)));
+            }
+            text
+        }
+        Expr::Unary(op, right) => format!("{} {}", op_text(op), expr(right)),
+        Expr::Call(name, args) => format!("{}({})", name, list(args)),
+        Expr::Index(collection, index) => format!("{}[{}]", expr(collection), expr(index)),
+        Expr::CallValue(callee, args) => format!("{}({})", expr(callee), list(args)),
+        Expr::Grouping(inner) => format!("({})", expr(inner)),
+    }
+}
+
+fn list(exprs: &[Expr]) -> String {
+    exprs.iter().map(expr).collect::<Vec<_>>().join(", ")
+}
+
+fn op_text(op: &Op) -> &'static str {
+    match op {
+        Op::Add => "+",
+        Op::Sub => "-",
+        Op::Mul => "*",
+        Op::Div => "/",
+        Op::Mod => "%",
+        Op::Equal => "==",
+        Op::NotEqual => "!=",
+        Op::Lt => "<",
+        Op::Gt => ">",
+        Op::LtEq => "<=",
+        Op::GtEq => ">=",
+        Op::And => "and",
+        Op::Or => "or",
+        Op::Coalesce => "??",
+        Op::Not => "not",
+    }
+}
+
+/// Quotes a string, escaping what the lexer would not read back verbatim.
+fn string_literal(text: &str) -> String {
+    let mut out = String::from("\"");
+    for ch in text.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\0"),
+            ch if ch.is_control() => out.push_str(&format!("\\u{{{:X}}}", ch as u32)),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    /// Formats `src`, checks the result against `expected`, and checks that
+    /// formatting the result again changes nothing.
+    fn assert_formats(src: &str, expected: &str) {
+        let once = crate::format_source(src.to_string(), false).unwrap();
+        assert_eq!(once, expected);
+        let twice = crate::format_source(once.clone(), false).unwrap();
+        assert_eq!(twice, once, "formatting again changed the output");
+    }
+
+    #[test]
+    fn else_if_chains_print_as_elseif() {
+        assert_formats(
+            "if a then print(1) elseif b then print(2) else print(3) end",
+            "if a then\n    print(1)\nelseif b then\n    print(2)\nelse\n    print(3)\nend\n",
+        );
+        // An else branch holding more than the nested `if` stays an else.
+        assert_formats(
+            "if a then print(1) else print(4) if b then print(2) end end",
+            "if a then\n    print(1)\nelse\n    print(4)\n    if b then\n        print(2)\n    end\nend\n",
+        );
+    }
+
+    #[test]
+    fn bare_return_is_spelled_out_unless_last() {
+        assert_formats(
+            "fn f() do\nreturn\nprint(1)\nend",
+            "fn f() do\n    return nil\n    print(1)\nend\n",
+        );
+        assert_formats(
+            "fn f() do if a then return end return end",
+            "fn f() do\n    if a then\n        return\n    end\n    return\nend\n",
+        );
+    }
+
+    #[test]
+    fn for_clauses_keep_their_comma_lists() {
+        assert_formats(
+            "for let mod i = 0, let mod j = 9; i < j; i++, j-- do print(i) end",
+            "for let mod i = 0, let mod j = 9; i < j; i = i + 1, j = j - 1 do\n    print(i)\nend\n",
+        );
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        assert_formats(
+            r#"print("q\"b\\c\nd\te\u{1}")"#,
+            "print(\"q\\\"b\\\\c\\nd\\te\\u{1}\")\n",
+        );
+    }
+
+    #[test]
+    fn floats_read_back_as_floats() {
+        assert_formats(
+            "let xs = [1e300, 0.1, 2.0, 1.5e-7, 1e16, 3e0]",
+            "let xs = [1e300, 0.1, 2.0, 1.5e-7, 1e16, 3.0]\n",
+        );
+    }
+
+    #[test]
+    fn parentheses_are_kept() {
+        assert_formats(
+            "print((1+2)*3)\nprint(((a)))\nprint(1+(2*3))",
+            "print((1 + 2) * 3)\nprint(((a)))\nprint(1 + (2 * 3))\n",
+        );
+    }
+}
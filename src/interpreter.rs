@@ -1,35 +1,577 @@
-use crate::ast::{Expr, Op, Stmt};
-use std::collections::HashMap;
+use crate::ast::{Expr, Op, Param, Stmt, TypeTag};
+use crate::error::BloodError;
+use crate::format;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-#[derive(Debug, Clone, PartialEq)]
+/// The default for `Interpreter::with_max_collection_size`.
+const DEFAULT_MAX_COLLECTION_SIZE: usize = 100_000_000;
+
+/// A host function registered with `Interpreter::register_native`.
+pub type NativeFn = dyn Fn(Vec<ValueView>) -> Result<ValueView, String>;
+
+#[derive(Clone)]
 enum Value {
     Integer(i64),
+    Float(f64),
+    String(String),
     Boolean(bool),
     Nil,
+    Array(Vec<Value>),
+    Map(Map),
     Function {
         name: String,
-        params: Vec<String>,
-        body: Vec<Stmt>,
+        params: Rc<Vec<Param>>,
+        return_type: Option<TypeTag>,
+        body: Rc<Vec<Stmt>>,
+    },
+    Native {
+        name: String,
+        func: Rc<NativeFn>,
+    },
+    /// A function wrapped by `memoize`, with the results it has returned so
+    /// far keyed by their arguments.
+    Memoized {
+        name: String,
+        func: Rc<Value>,
+        cache: Rc<RefCell<HashMap<Vec<MemoKey>, Value>>>,
     },
 }
 
+/// The entries of a map in insertion order. Lookups scan the entries, which
+/// keeps iteration order the same on every run.
+#[derive(Clone, Default)]
+struct Map {
+    entries: Vec<(Value, Value)>,
+}
+
+impl Map {
+    fn get(&self, key: &Value) -> Option<&Value> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    /// Sets `key` to `value`. A key that is already present keeps its
+    /// place; a new one goes at the end.
+    fn insert(&mut self, key: Value, value: Value) {
+        match self.entries.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, slot)) => *slot = value,
+            None => self.entries.push((key, value)),
+        }
+    }
+
+    /// Takes `key` out of the map. The remaining entries keep their order.
+    fn remove(&mut self, key: &Value) -> Option<Value> {
+        let index = self.entries.iter().position(|(k, _)| k == key)?;
+        Some(self.entries.remove(index).1)
+    }
+}
+
+/// Maps are equal when they hold the same entries, whatever their order.
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries.len() == other.entries.len()
+            && self.entries.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+/// A hashable copy of an argument, used as part of a `memoize` cache key.
+/// Floats are keyed by their bits.
+#[derive(PartialEq, Eq, Hash)]
+enum MemoKey {
+    Integer(i64),
+    Float(u64),
+    String(String),
+    Boolean(bool),
+    Nil,
+    Array(Vec<MemoKey>),
+    Map(Vec<(MemoKey, MemoKey)>),
+}
+
+/// Values of different types are never equal, so `1 == "1"` is false.
+/// Arrays compare element by element, maps entry by entry, and functions by
+/// identity: a function is only equal to the very definition it came from.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Nil, Value::Nil) => true,
+            (Value::Array(a), Value::Array(b)) => a == b,
+            (Value::Map(a), Value::Map(b)) => a == b,
+            (Value::Function { body: a, .. }, Value::Function { body: b, .. }) => Rc::ptr_eq(a, b),
+            (Value::Native { func: a, .. }, Value::Native { func: b, .. }) => Rc::ptr_eq(a, b),
+            (Value::Memoized { cache: a, .. }, Value::Memoized { cache: b, .. }) => {
+                Rc::ptr_eq(a, b)
+            }
+            _ => false,
+        }
+    }
+}
+
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Value::Integer(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{:?}", v),
+            Value::String(v) => write!(f, "{}", v),
             Value::Boolean(v) => write!(f, "{}", v),
             Value::Nil => write!(f, "nil"),
-            Value::Function { name, .. } => write!(f, "<fn {}>", name),
+            Value::Array(elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }
+            Value::Map(map) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, value)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Function { name, params, .. } => write!(f, "<fn {}/{}>", name, params.len()),
+            Value::Native { name, .. } => write!(f, "<native fn {}>", name),
+            Value::Memoized { name, .. } => write!(f, "<memoized fn {}>", name),
+        }
+    }
+}
+
+/// A copy of a Blood value that host code can inspect or build. Functions
+/// only expose their name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueView {
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Boolean(bool),
+    Nil,
+    Array(Vec<ValueView>),
+    /// A map's entries in insertion order.
+    Map(Vec<(ValueView, ValueView)>),
+    Function(String),
+}
+
+impl From<&Value> for ValueView {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Integer(v) => ValueView::Integer(*v),
+            Value::Float(v) => ValueView::Float(*v),
+            Value::String(v) => ValueView::String(v.clone()),
+            Value::Boolean(v) => ValueView::Boolean(*v),
+            Value::Nil => ValueView::Nil,
+            Value::Array(elements) => ValueView::Array(elements.iter().map(Self::from).collect()),
+            Value::Map(map) => ValueView::Map(
+                map.entries
+                    .iter()
+                    .map(|(k, v)| (Self::from(k), Self::from(v)))
+                    .collect(),
+            ),
+            Value::Function { name, .. }
+            | Value::Native { name, .. }
+            | Value::Memoized { name, .. } => ValueView::Function(name.clone()),
         }
     }
 }
 
+impl TryFrom<ValueView> for Value {
+    type Error = String;
+
+    fn try_from(view: ValueView) -> Result<Self, String> {
+        Ok(match view {
+            ValueView::Integer(v) => Value::Integer(v),
+            ValueView::Float(v) => Value::Float(v),
+            ValueView::String(v) => Value::String(v),
+            ValueView::Boolean(v) => Value::Boolean(v),
+            ValueView::Nil => Value::Nil,
+            ValueView::Array(elements) => Value::Array(
+                elements
+                    .into_iter()
+                    .map(Value::try_from)
+                    .collect::<Result<_, _>>()?,
+            ),
+            ValueView::Map(entries) => {
+                let mut map = Map::default();
+                for (key, value) in entries {
+                    let key = Value::try_from(key)?;
+                    check_key(&key).map_err(|e| e.to_string())?;
+                    map.insert(key, Value::try_from(value)?);
+                }
+                Value::Map(map)
+            }
+            ValueView::Function(name) => {
+                return Err(format!(
+                    "Cannot create function '{}' from host code; define it in a script instead.",
+                    name
+                ));
+            }
+        })
+    }
+}
+
 struct Variable {
     value: Value,
     mutable: bool,
 }
 
+/// The variables declared in one block, kept in declaration order so that
+/// resolved slots can index them directly.
+#[derive(Default)]
+struct Scope {
+    names: Vec<String>,
+    vars: Vec<Variable>,
+}
+
+impl Scope {
+    fn position(&self, name: &str) -> Option<usize> {
+        self.names.iter().rposition(|n| n == name)
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.position(name).is_some()
+    }
+
+    fn get(&self, name: &str) -> Option<&Variable> {
+        self.position(name).map(|i| &self.vars[i])
+    }
+
+    fn get_mut(&mut self, name: &str) -> Option<&mut Variable> {
+        self.position(name).map(|i| &mut self.vars[i])
+    }
+
+    fn insert(&mut self, name: String, var: Variable) {
+        self.names.push(name);
+        self.vars.push(var);
+    }
+}
+
+/// The signature shared by all built-in functions: the evaluated arguments
+/// in, the result out.
+type BuiltinFn = fn(&mut Interpreter, Vec<Value>) -> Result<Value, BloodError>;
+
+/// A built-in function. `usage` shows how to call it, with `?` marking an
+/// optional parameter and `...` any number of values.
+struct Builtin {
+    name: &'static str,
+    usage: &'static str,
+    func: BuiltinFn,
+}
+
+/// Every built-in function, in the order `:builtins` lists them in the REPL.
+const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "error",
+        usage: "error(message)",
+        func: Interpreter::native_error,
+    },
+    Builtin {
+        name: "assert_eq",
+        usage: "assert_eq(actual, expected, message?)",
+        func: Interpreter::native_assert_eq,
+    },
+    Builtin {
+        name: "exit",
+        usage: "exit(code?)",
+        func: Interpreter::native_exit,
+    },
+    Builtin {
+        name: "write",
+        usage: "write(values...)",
+        func: Interpreter::native_write,
+    },
+    Builtin {
+        name: "eprint",
+        usage: "eprint(values...)",
+        func: Interpreter::native_eprint,
+    },
+    Builtin {
+        name: "debug",
+        usage: "debug(x)",
+        func: Interpreter::native_debug,
+    },
+    Builtin {
+        name: "pretty_print",
+        usage: "pretty_print(x)",
+        func: Interpreter::native_pretty_print,
+    },
+    Builtin {
+        name: "format",
+        usage: "format(template, values...)",
+        func: Interpreter::native_format,
+    },
+    Builtin {
+        name: "sqrt",
+        usage: "sqrt(x)",
+        func: Interpreter::native_sqrt,
+    },
+    Builtin {
+        name: "floor",
+        usage: "floor(x)",
+        func: Interpreter::native_floor,
+    },
+    Builtin {
+        name: "ceil",
+        usage: "ceil(x)",
+        func: Interpreter::native_ceil,
+    },
+    Builtin {
+        name: "round",
+        usage: "round(x)",
+        func: Interpreter::native_round,
+    },
+    Builtin {
+        name: "pow",
+        usage: "pow(base, exp)",
+        func: Interpreter::native_pow,
+    },
+    Builtin {
+        name: "divmod",
+        usage: "divmod(a, b)",
+        func: Interpreter::native_divmod,
+    },
+    Builtin {
+        name: "min",
+        usage: "min(values...)",
+        func: Interpreter::native_min,
+    },
+    Builtin {
+        name: "max",
+        usage: "max(values...)",
+        func: Interpreter::native_max,
+    },
+    Builtin {
+        name: "sum",
+        usage: "sum(xs)",
+        func: Interpreter::native_sum,
+    },
+    Builtin {
+        name: "product",
+        usage: "product(xs)",
+        func: Interpreter::native_product,
+    },
+    Builtin {
+        name: "sort",
+        usage: "sort(xs, descending?)",
+        func: Interpreter::native_sort,
+    },
+    Builtin {
+        name: "clone",
+        usage: "clone(x)",
+        func: Interpreter::native_clone,
+    },
+    Builtin {
+        name: "contains",
+        usage: "contains(xs, x)",
+        func: Interpreter::native_contains,
+    },
+    Builtin {
+        name: "index_of",
+        usage: "index_of(xs, x)",
+        func: Interpreter::native_index_of,
+    },
+    Builtin {
+        name: "slice",
+        usage: "slice(xs, start, end)",
+        func: Interpreter::native_slice,
+    },
+    Builtin {
+        name: "reverse",
+        usage: "reverse(xs)",
+        func: Interpreter::native_reverse,
+    },
+    Builtin {
+        name: "zip",
+        usage: "zip(xs, ys)",
+        func: Interpreter::native_zip,
+    },
+    Builtin {
+        name: "enumerate",
+        usage: "enumerate(xs)",
+        func: Interpreter::native_enumerate,
+    },
+    Builtin {
+        name: "keys",
+        usage: "keys(m)",
+        func: Interpreter::native_keys,
+    },
+    Builtin {
+        name: "values",
+        usage: "values(m)",
+        func: Interpreter::native_values,
+    },
+    Builtin {
+        name: "get",
+        usage: "get(m, key, default?)",
+        func: Interpreter::native_get,
+    },
+    Builtin {
+        name: "set",
+        usage: "set(m, key, value)",
+        func: Interpreter::native_set,
+    },
+    Builtin {
+        name: "remove",
+        usage: "remove(xs, key)",
+        func: Interpreter::native_remove,
+    },
+    Builtin {
+        name: "join",
+        usage: "join(xs, sep)",
+        func: Interpreter::native_join,
+    },
+    Builtin {
+        name: "ord",
+        usage: "ord(c)",
+        func: Interpreter::native_ord,
+    },
+    Builtin {
+        name: "chr",
+        usage: "chr(n)",
+        func: Interpreter::native_chr,
+    },
+    Builtin {
+        name: "replace",
+        usage: "replace(s, from, to)",
+        func: Interpreter::native_replace,
+    },
+    Builtin {
+        name: "trim",
+        usage: "trim(s)",
+        func: Interpreter::native_trim,
+    },
+    Builtin {
+        name: "trim_start",
+        usage: "trim_start(s)",
+        func: Interpreter::native_trim_start,
+    },
+    Builtin {
+        name: "trim_end",
+        usage: "trim_end(s)",
+        func: Interpreter::native_trim_end,
+    },
+    Builtin {
+        name: "map",
+        usage: "map(xs, f)",
+        func: Interpreter::native_map,
+    },
+    Builtin {
+        name: "filter",
+        usage: "filter(xs, pred)",
+        func: Interpreter::native_filter,
+    },
+    Builtin {
+        name: "reduce",
+        usage: "reduce(xs, init, f)",
+        func: Interpreter::native_reduce,
+    },
+    Builtin {
+        name: "clock",
+        usage: "clock()",
+        func: Interpreter::native_clock,
+    },
+    Builtin {
+        name: "random",
+        usage: "random(lo, hi)",
+        func: Interpreter::native_random,
+    },
+    Builtin {
+        name: "seed",
+        usage: "seed(n)",
+        func: Interpreter::native_seed,
+    },
+    Builtin {
+        name: "is_nil",
+        usage: "is_nil(x)",
+        func: |_, args| type_check("is_nil", args, |v| matches!(v, Value::Nil)),
+    },
+    Builtin {
+        name: "is_bool",
+        usage: "is_bool(x)",
+        func: |_, args| type_check("is_bool", args, |v| matches!(v, Value::Boolean(_))),
+    },
+    Builtin {
+        name: "is_number",
+        usage: "is_number(x)",
+        func: |_, args| {
+            type_check("is_number", args, |v| {
+                matches!(v, Value::Integer(_) | Value::Float(_))
+            })
+        },
+    },
+    Builtin {
+        name: "is_integer",
+        usage: "is_integer(x)",
+        func: |_, args| type_check("is_integer", args, |v| matches!(v, Value::Integer(_))),
+    },
+    Builtin {
+        name: "is_float",
+        usage: "is_float(x)",
+        func: |_, args| type_check("is_float", args, |v| matches!(v, Value::Float(_))),
+    },
+    Builtin {
+        name: "is_string",
+        usage: "is_string(x)",
+        func: |_, args| type_check("is_string", args, |v| matches!(v, Value::String(_))),
+    },
+    Builtin {
+        name: "is_array",
+        usage: "is_array(x)",
+        func: |_, args| type_check("is_array", args, |v| matches!(v, Value::Array(_))),
+    },
+    Builtin {
+        name: "is_map",
+        usage: "is_map(x)",
+        func: |_, args| type_check("is_map", args, |v| matches!(v, Value::Map(_))),
+    },
+    Builtin {
+        name: "is_function",
+        usage: "is_function(x)",
+        func: |_, args| {
+            type_check("is_function", args, |v| {
+                matches!(
+                    v,
+                    Value::Function { .. } | Value::Native { .. } | Value::Memoized { .. }
+                )
+            })
+        },
+    },
+    Builtin {
+        name: "memoize",
+        usage: "memoize(f)",
+        func: Interpreter::native_memoize,
+    },
+    Builtin {
+        name: "bool",
+        usage: "bool(x)",
+        func: Interpreter::native_bool,
+    },
+    Builtin {
+        name: "all",
+        usage: "all(xs)",
+        func: Interpreter::native_all,
+    },
+    Builtin {
+        name: "any",
+        usage: "any(xs)",
+        func: Interpreter::native_any,
+    },
+];
+
 #[derive(Clone)]
 enum ExecutionResult {
     Normal,
@@ -38,89 +580,420 @@ enum ExecutionResult {
     Return(Value),
 }
 
+/// How one pass through a loop body ended.
+enum LoopFlow {
+    /// The body finished or hit `continue`; the loop goes on.
+    Next,
+    Break,
+    Return(Value),
+}
+
 pub struct Interpreter {
     globals: HashMap<String, Variable>,
+    builtins: HashMap<&'static str, BuiltinFn>,
 
-    call_stack: Vec<Vec<HashMap<String, Variable>>>,
+    call_stack: Vec<Vec<Scope>>,
 
     loop_depth: usize,
     function_depth: usize,
+
+    // Files currently being imported, innermost last; the running script sits
+    // at the bottom when its path is known.
+    import_stack: Vec<PathBuf>,
+    imported: HashSet<PathBuf>,
+
+    strict_returns: bool,
+    truthy_conditions: bool,
+    newline_terminators: bool,
+    echo_expressions: bool,
+    // Where `print`, `write` and `pretty_print` send their output.
+    out: Box<dyn Write>,
+    // How `print` and `write` render booleans and nil.
+    true_text: String,
+    false_text: String,
+    nil_text: String,
+
+    // Reference point for clock().
+    started: Instant,
+    // xorshift64 state behind random(); never zero.
+    rng_state: u64,
+
+    steps: usize,
+    step_limit: Option<usize>,
+    // Iteration count after which a `loop` warns that it may never end.
+    loop_warning: Option<usize>,
+    // Largest array (in elements) or string (in bytes) an operation may build.
+    max_collection_size: usize,
+
+    // Body of the user function being executed, and the arguments of a
+    // pending tail call to it (see `Stmt::Return`).
+    current_body: Option<Rc<Vec<Stmt>>>,
+    tail_call: Option<Vec<Value>>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
         Self {
             globals: HashMap::new(),
-            call_stack: vec![vec![HashMap::new()]],
+            builtins: BUILTINS.iter().map(|b| (b.name, b.func)).collect(),
+            call_stack: vec![vec![Scope::default()]],
             loop_depth: 0,
             function_depth: 0,
+            import_stack: Vec::new(),
+            imported: HashSet::new(),
+            strict_returns: false,
+            truthy_conditions: false,
+            newline_terminators: false,
+            echo_expressions: false,
+            out: Box::new(io::stdout()),
+            true_text: "true".to_string(),
+            false_text: "false".to_string(),
+            nil_text: "nil".to_string(),
+            started: Instant::now(),
+            rng_state: time_seed(),
+            steps: 0,
+            step_limit: None,
+            loop_warning: None,
+            max_collection_size: DEFAULT_MAX_COLLECTION_SIZE,
+            current_body: None,
+            tail_call: None,
+        }
+    }
+
+    /// Usage lines for the built-in functions, such as `"sqrt(x)"`.
+    pub fn builtins() -> impl Iterator<Item = &'static str> {
+        BUILTINS.iter().map(|b| b.usage)
+    }
+
+    /// Records the path of the running script so `import` can resolve paths
+    /// relative to it.
+    pub fn with_script_path(mut self, path: &str) -> Self {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        self.import_stack.push(path);
+        self
+    }
+
+    /// When enabled, using the result of a function that finished without
+    /// executing `return` is a runtime error instead of silently yielding `nil`.
+    pub fn with_strict_returns(mut self, strict: bool) -> Self {
+        self.strict_returns = strict;
+        self
+    }
+
+    /// When enabled, `if` and `while` accept any value as a condition, using
+    /// `is_truthy` instead of requiring a boolean.
+    pub fn with_truthy_conditions(mut self, truthy: bool) -> Self {
+        self.truthy_conditions = truthy;
+        self
+    }
+
+    /// Parses imported files with newline terminators, matching the script
+    /// that imports them.
+    pub fn with_newline_terminators(mut self, enabled: bool) -> Self {
+        self.newline_terminators = enabled;
+        self
+    }
+
+    /// When enabled, an expression statement outside any function prints its
+    /// value, unless that value is `nil`. This is what the REPL uses to show
+    /// the result of `1 + 2` or `sqrt(2)` without a `print`.
+    pub fn with_expression_echo(mut self, enabled: bool) -> Self {
+        self.echo_expressions = enabled;
+        self
+    }
+
+    /// Sends the output of `print`, `write` and `pretty_print` to `out`
+    /// instead of stdout. `eprint`, `debug` and warnings still go to stderr.
+    pub fn with_output(mut self, out: impl Write + 'static) -> Self {
+        self.out = Box::new(out);
+        self
+    }
+
+    /// Sets the text `print` and `write` show for `true` and `false`, such as
+    /// `"1"` and `"0"`. Booleans inside arrays are shown the same way.
+    pub fn with_bool_format(mut self, true_text: &str, false_text: &str) -> Self {
+        self.true_text = true_text.to_string();
+        self.false_text = false_text.to_string();
+        self
+    }
+
+    /// Sets the text `print` and `write` show for `nil`, such as `""`.
+    pub fn with_nil_format(mut self, text: &str) -> Self {
+        self.nil_text = text.to_string();
+        self
+    }
+
+    /// Reads a global variable for host code embedding the interpreter.
+    pub fn get_global(&self, name: &str) -> Option<ValueView> {
+        self.globals
+            .get(name)
+            .map(|var| ValueView::from(&var.value))
+    }
+
+    /// Sets a global variable from host code, typically before running a
+    /// script. A new global is mutable so the script can update it; an
+    /// existing one keeps its mutability.
+    pub fn set_global(&mut self, name: &str, value: ValueView) -> Result<(), BloodError> {
+        let value = Value::try_from(value)?;
+        match self.globals.get_mut(name) {
+            Some(var) => var.value = value,
+            None => {
+                self.globals.insert(
+                    name.to_string(),
+                    Variable {
+                        value,
+                        mutable: true,
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Caps the number of statements and expressions a program may evaluate,
+    /// so untrusted code cannot run forever. Going over the limit is a runtime
+    /// error.
+    pub fn with_step_limit(mut self, limit: usize) -> Self {
+        self.step_limit = Some(limit);
+        self
+    }
+
+    /// Caps the size of the arrays (in elements) and strings (in bytes) that
+    /// operations such as `+`, `*`, `join` and `replace` may build, so a
+    /// script cannot exhaust the host's memory. Going over it is a runtime
+    /// error. Defaults to 100 million.
+    pub fn with_max_collection_size(mut self, size: usize) -> Self {
+        self.max_collection_size = size;
+        self
+    }
+
+    /// Prints a warning to stderr when a single `loop do ... end` reaches
+    /// `iterations` iterations, as a hint that it is missing a `break`. The
+    /// loop keeps running.
+    pub fn with_loop_warning(mut self, iterations: usize) -> Self {
+        self.loop_warning = Some(iterations);
+        self
+    }
+
+    /// Exposes a host function to scripts as a global named `name`. The
+    /// function receives the call's arguments and checks their count itself.
+    ///
+    /// Fails if `name` is the name of a built-in, since calls to it would
+    /// always reach the built-in.
+    pub fn register_native(&mut self, name: &str, func: Box<NativeFn>) -> Result<(), BloodError> {
+        let value = Value::Native {
+            name: name.to_string(),
+            func: Rc::from(func),
+        };
+        self.check_builtin_name(name, &value)?;
+        self.globals.insert(
+            name.to_string(),
+            Variable {
+                value,
+                mutable: false,
+            },
+        );
+        Ok(())
+    }
+
+    /// Runs a program. After an error the interpreter is back at the top
+    /// level, so it can run further programs with the globals defined so far.
+    pub fn interpret(&mut self, statements: Vec<Stmt>) -> Result<(), BloodError> {
+        let result = self.run_program(&statements);
+        if result.is_err() {
+            self.call_stack.truncate(1);
+            self.call_stack[0].truncate(1);
+            self.loop_depth = 0;
+            self.function_depth = 0;
         }
+        result
     }
 
-    pub fn interpret(&mut self, statements: Vec<Stmt>) -> Result<(), String> {
+    fn run_program(&mut self, statements: &[Stmt]) -> Result<(), BloodError> {
+        // Top-level functions are defined before anything else runs, so they
+        // can be called from code above their definition.
         for stmt in statements {
+            if let Stmt::Fn { .. } = stmt {
+                self.execute_stmt(stmt)?;
+            }
+        }
+        for stmt in statements.iter().filter(|s| !matches!(s, Stmt::Fn { .. })) {
             match self.execute_stmt(stmt)? {
                 ExecutionResult::Normal => {}
                 ExecutionResult::Break => {
-                    return Err("Runtime error: 'break' used outside of loop".to_string());
+                    return Err(BloodError::Runtime(
+                        "Runtime error: 'break' used outside of loop".to_string(),
+                    ));
                 }
                 ExecutionResult::Continue => {
-                    return Err("Runtime error: 'continue' used outside of loop".to_string());
+                    return Err(BloodError::Runtime(
+                        "Runtime error: 'continue' used outside of loop".to_string(),
+                    ));
                 }
                 ExecutionResult::Return(_) => {
-                    return Err("Runtime error: 'return' used outside of function".to_string());
+                    return Err(BloodError::Runtime(
+                        "Runtime error: 'return' used outside of function".to_string(),
+                    ));
                 }
             }
         }
         Ok(())
     }
 
-    fn current_frame_mut(&mut self) -> &mut Vec<HashMap<String, Variable>> {
+    /// Runs a program like `interpret`, returning the value of its final
+    /// statement when that is an expression, or `nil` otherwise.
+    pub fn interpret_value(&mut self, mut statements: Vec<Stmt>) -> Result<ValueView, BloodError> {
+        let last = match statements.last() {
+            Some(Stmt::ExprStmt(_)) => statements.pop(),
+            _ => None,
+        };
+        self.interpret(statements)?;
+        match last {
+            Some(Stmt::ExprStmt(expr)) => Ok(ValueView::from(&self.eval_expr(&expr)?)),
+            _ => Ok(ValueView::Nil),
+        }
+    }
+
+    /// Renders a value for output, like `Display` but with the configured
+    /// text for booleans and nil.
+    fn format_value(&self, value: &Value) -> String {
+        match value {
+            Value::Boolean(true) => self.true_text.clone(),
+            Value::Boolean(false) => self.false_text.clone(),
+            Value::Nil => self.nil_text.clone(),
+            Value::Array(elements) => {
+                let elements: Vec<String> = elements.iter().map(|e| self.format_value(e)).collect();
+                format!("[{}]", elements.join(", "))
+            }
+            Value::Map(map) => {
+                let entries: Vec<String> = map
+                    .entries
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", self.format_value(k), self.format_value(v)))
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+            _ => value.to_string(),
+        }
+    }
+
+    /// Renders a value for `pretty_print`, as the element of a collection
+    /// nested `depth` levels deep. An array or map holding other arrays or
+    /// maps puts each element on its own line, indented two spaces per level;
+    /// any other collection stays on one line.
+    fn pretty(&self, value: &Value, depth: usize) -> String {
+        let indent = "  ".repeat(depth + 1);
+        match value {
+            Value::Array(elements) if elements.iter().any(is_collection) => {
+                let lines: Vec<String> = elements
+                    .iter()
+                    .map(|e| format!("{}{}", indent, self.pretty(e, depth + 1)))
+                    .collect();
+                format!("[\n{}\n{}]", lines.join(",\n"), "  ".repeat(depth))
+            }
+            Value::Map(map) if map.entries.iter().any(|(_, v)| is_collection(v)) => {
+                let lines: Vec<String> = map
+                    .entries
+                    .iter()
+                    .map(|(k, v)| {
+                        format!(
+                            "{}{}: {}",
+                            indent,
+                            self.format_value(k),
+                            self.pretty(v, depth + 1)
+                        )
+                    })
+                    .collect();
+                format!("{{\n{}\n{}}}", lines.join(",\n"), "  ".repeat(depth))
+            }
+            _ => self.format_value(value),
+        }
+    }
+
+    /// Writes `text` and a newline to the output sink.
+    fn print_line(&mut self, text: &str) -> Result<(), BloodError> {
+        writeln!(self.out, "{}", text).map_err(output_error)
+    }
+
+    fn current_frame_mut(&mut self) -> &mut Vec<Scope> {
         self.call_stack.last_mut().unwrap()
     }
 
-    fn current_frame(&self) -> &Vec<HashMap<String, Variable>> {
+    fn current_frame(&self) -> &Vec<Scope> {
         self.call_stack.last().unwrap()
     }
 
     fn enter_scope(&mut self) {
-        self.current_frame_mut().push(HashMap::new());
+        self.current_frame_mut().push(Scope::default());
     }
 
     fn exit_scope(&mut self) {
         self.current_frame_mut().pop();
     }
 
-    fn define_variable(&mut self, name: String, value: Value, mutable: bool) -> Result<(), String> {
-        if self.function_depth == 0 {
+    /// Fails if a function is about to be stored under the name of a
+    /// built-in. Calls by that name always reach the built-in, so the function
+    /// could never be called by it. Other values may use the name.
+    fn check_builtin_name(&self, name: &str, value: &Value) -> Result<(), BloodError> {
+        let is_function = matches!(
+            value,
+            Value::Function { .. } | Value::Native { .. } | Value::Memoized { .. }
+        );
+        if is_function && self.builtins.contains_key(name) {
+            return Err(BloodError::Runtime(format!(
+                "Runtime Error: Cannot redefine built-in '{}'.",
+                name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Declares `name` in the innermost scope. Only declarations at the top
+    /// level of the script become globals; a `let` inside any block is local
+    /// to that block and may shadow a global or outer variable of the same name.
+    fn define_variable(
+        &mut self,
+        name: String,
+        value: Value,
+        mutable: bool,
+    ) -> Result<(), BloodError> {
+        self.check_builtin_name(&name, &value)?;
+        if self.function_depth == 0 && self.current_frame().len() == 1 {
             if self.globals.contains_key(&name) {
-                return Err(format!(
+                return Err(BloodError::Runtime(format!(
                     "Runtime Error: Global variable '{}' already declared.",
                     name
-                ));
+                )));
             }
             self.globals.insert(name, Variable { value, mutable });
         } else {
             let current_scope = self.current_frame_mut().last_mut().unwrap();
-            if current_scope.contains_key(&name) {
-                return Err(format!(
+            if current_scope.contains(&name) {
+                return Err(BloodError::Runtime(format!(
                     "Runtime Error: Variable '{}' already declared in this scope.",
                     name
-                ));
+                )));
             }
             current_scope.insert(name, Variable { value, mutable });
         }
         Ok(())
     }
 
-    fn assign_variable(&mut self, name: &str, value: Value) -> Result<(), String> {
+    fn assign_variable(&mut self, name: &str, value: Value) -> Result<(), BloodError> {
+        self.check_builtin_name(name, &value)?;
         for scope in self.current_frame_mut().iter_mut().rev() {
             if let Some(var) = scope.get_mut(name) {
                 if !var.mutable {
-                    return Err(format!(
+                    return Err(BloodError::Runtime(format!(
                         "Runtime Error: Cannot reassign immutable variable '{}'.",
                         name
-                    ));
+                    )));
                 }
                 var.value = value;
                 return Ok(());
@@ -129,19 +1002,19 @@ impl Interpreter {
 
         if let Some(var) = self.globals.get_mut(name) {
             if !var.mutable {
-                return Err(format!(
+                return Err(BloodError::Runtime(format!(
                     "Runtime Error: Cannot reassign immutable variable '{}'.",
                     name
-                ));
+                )));
             }
             var.value = value;
             return Ok(());
         }
 
-        Err(format!("Runtime Error: Variable '{}' not found.", name))
+        Err(BloodError::UndefinedVariable(name.to_string()))
     }
 
-    fn get_variable(&self, name: &str) -> Result<Value, String> {
+    fn get_variable(&self, name: &str) -> Result<Value, BloodError> {
         for scope in self.current_frame().iter().rev() {
             if let Some(var) = scope.get(name) {
                 return Ok(var.value.clone());
@@ -152,10 +1025,56 @@ impl Interpreter {
             return Ok(var.value.clone());
         }
 
-        Err(format!("Runtime Error: Variable '{}' not defined.", name))
+        Err(BloodError::UndefinedVariable(name.to_string()))
+    }
+
+    /// Reads a variable the resolver has already located, `depth` scopes out
+    /// from the innermost one. Falls back to a lookup by name if the frame
+    /// doesn't have the expected shape.
+    fn get_resolved(&self, name: &str, depth: usize, slot: usize) -> Result<Value, BloodError> {
+        let frame = self.current_frame();
+        let var = frame
+            .len()
+            .checked_sub(depth + 1)
+            .and_then(|i| frame[i].vars.get(slot));
+        match var {
+            Some(var) => Ok(var.value.clone()),
+            None => self.get_variable(name),
+        }
+    }
+
+    fn condition(&self, value: Value, error: &str) -> Result<bool, BloodError> {
+        match value {
+            Value::Boolean(b) => Ok(b),
+            _ if self.truthy_conditions => Ok(is_truthy(&value)),
+            _ => Err(BloodError::Runtime(error.to_string())),
+        }
+    }
+
+    /// Fails if a collection of `size` elements or bytes would be over the
+    /// configured limit. Checked before building the collection.
+    fn check_size(&self, size: usize) -> Result<(), BloodError> {
+        if size > self.max_collection_size {
+            return Err(BloodError::Runtime(format!(
+                "Runtime Error: collection size limit exceeded ({} > {}).",
+                size, self.max_collection_size
+            )));
+        }
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<(), BloodError> {
+        self.steps += 1;
+        match self.step_limit {
+            Some(limit) if self.steps > limit => Err(BloodError::Runtime(
+                "Runtime Error: execution step limit exceeded.".to_string(),
+            )),
+            _ => Ok(()),
+        }
     }
 
-    fn execute_stmt(&mut self, stmt: Stmt) -> Result<ExecutionResult, String> {
+    fn execute_stmt(&mut self, stmt: &Stmt) -> Result<ExecutionResult, BloodError> {
+        self.step()?;
         match stmt {
             Stmt::Let {
                 name,
@@ -163,18 +1082,52 @@ impl Interpreter {
                 value,
             } => {
                 let val = self.eval_expr(value)?;
-                self.define_variable(name, val, mutable)?;
+                self.define_variable(name.clone(), val, *mutable)?;
+            }
+            Stmt::LetMulti {
+                names,
+                mutable,
+                value,
+            } => {
+                let values = match self.eval_expr(value)? {
+                    Value::Array(values) => values,
+                    _ => {
+                        return Err(BloodError::Runtime(
+                            "Runtime Error: Multiple assignment expects an array value."
+                                .to_string(),
+                        ));
+                    }
+                };
+                if values.len() != names.len() {
+                    return Err(BloodError::Runtime(format!(
+                        "Runtime Error: Cannot bind {} values to {} names.",
+                        values.len(),
+                        names.len()
+                    )));
+                }
+                for (name, val) in names.iter().zip(values) {
+                    self.define_variable(name.clone(), val, *mutable)?;
+                }
             }
             Stmt::Assign { name, value } => {
                 let val = self.eval_expr(value)?;
-                self.assign_variable(&name, val)?;
+                self.assign_variable(name, val)?;
             }
             Stmt::Print(expr) => {
                 let val = self.eval_expr(expr)?;
-                println!("{}", val);
+                let text = self.format_value(&val);
+                self.print_line(&text)?;
             }
             Stmt::ExprStmt(expr) => {
-                self.eval_expr(expr)?;
+                let val = match expr {
+                    Expr::Call(name, args) => self.call_function(name, args, false)?,
+                    Expr::CallValue(callee, args) => self.call_expr(callee, args, false)?,
+                    _ => self.eval_expr(expr)?,
+                };
+                if self.echo_expressions && self.function_depth == 0 && val != Value::Nil {
+                    let text = self.format_value(&val);
+                    self.print_line(&text)?;
+                }
             }
             Stmt::If {
                 condition,
@@ -182,10 +1135,8 @@ impl Interpreter {
                 else_branch,
             } => {
                 let cond_val = self.eval_expr(condition)?;
-                let cond_bool = match cond_val {
-                    Value::Boolean(b) => b,
-                    _ => return Err("Runtime error: condition must be boolean".to_string()),
-                };
+                let cond_bool =
+                    self.condition(cond_val, "Runtime error: condition must be boolean")?;
 
                 if cond_bool {
                     self.enter_scope();
@@ -209,265 +1160,2513 @@ impl Interpreter {
                     self.exit_scope();
                 }
             }
-            Stmt::While { condition, body } => {
+            Stmt::While {
+                condition,
+                body,
+                else_branch,
+            } => {
                 self.loop_depth += 1;
-                loop {
-                    let cond_val = self.eval_expr(condition.clone())?;
-                    let cond_bool = match cond_val {
-                        Value::Boolean(b) => b,
-                        _ => {
-                            return Err(
-                                "Runtime error: while condition must be boolean".to_string()
-                            );
-                        }
-                    };
-
+                let flow = loop {
+                    let cond_val = self.eval_expr(condition)?;
+                    let cond_bool =
+                        self.condition(cond_val, "Runtime error: while condition must be boolean")?;
                     if !cond_bool {
-                        break;
+                        break LoopFlow::Next;
+                    }
+                    match self.run_loop_body(body)? {
+                        LoopFlow::Next => {}
+                        flow => break flow,
                     }
+                };
+                self.loop_depth -= 1;
 
+                if let LoopFlow::Return(v) = flow {
+                    return Ok(ExecutionResult::Return(v));
+                }
+                if let Some(else_stmts) = else_branch
+                    && !matches!(flow, LoopFlow::Break)
+                {
                     self.enter_scope();
-                    let mut flow_break = false;
-                    let mut flow_return = None;
-
-                    for s in &body {
-                        match self.execute_stmt(s.clone())? {
-                            ExecutionResult::Normal => {}
-                            ExecutionResult::Break => {
-                                flow_break = true;
-                                break;
-                            }
-                            ExecutionResult::Continue => {
-                                break;
-                            }
-                            ExecutionResult::Return(v) => {
-                                flow_return = Some(v);
-                                break;
-                            }
+                    for s in else_stmts {
+                        let res = self.execute_stmt(s)?;
+                        if !matches!(res, ExecutionResult::Normal) {
+                            self.exit_scope();
+                            return Ok(res);
                         }
                     }
                     self.exit_scope();
-
-                    if let Some(v) = flow_return {
-                        self.loop_depth -= 1;
-                        return Ok(ExecutionResult::Return(v));
-                    }
-                    if flow_break {
-                        break;
-                    }
                 }
-                self.loop_depth -= 1;
             }
             Stmt::Loop { body } => {
                 self.loop_depth += 1;
-                loop {
-                    self.enter_scope();
-                    let mut flow_break = false;
-                    let mut flow_return = None;
-
-                    for s in &body {
-                        match self.execute_stmt(s.clone())? {
-                            ExecutionResult::Normal => {}
-                            ExecutionResult::Break => {
-                                flow_break = true;
-                                break;
-                            }
-                            ExecutionResult::Continue => {
-                                break;
-                            }
-                            ExecutionResult::Return(v) => {
-                                flow_return = Some(v);
-                                break;
-                            }
-                        }
+                let mut iterations = 0;
+                let flow = loop {
+                    // An empty body evaluates nothing, so count the iteration itself.
+                    self.step()?;
+                    iterations += 1;
+                    if self.loop_warning == Some(iterations) {
+                        eprintln!(
+                            "Warning: loop has run {} times without a break; it may never end.",
+                            iterations
+                        );
                     }
-                    self.exit_scope();
-
-                    if let Some(v) = flow_return {
-                        self.loop_depth -= 1;
-                        return Ok(ExecutionResult::Return(v));
+                    match self.run_loop_body(body)? {
+                        LoopFlow::Next => {}
+                        flow => break flow,
+                    }
+                };
+                self.loop_depth -= 1;
+                if let LoopFlow::Return(v) = flow {
+                    return Ok(ExecutionResult::Return(v));
+                }
+            }
+            Stmt::Repeat { count, body } => {
+                let times = match self.eval_expr(count)? {
+                    Value::Integer(n) if n >= 0 => n,
+                    Value::Integer(n) => {
+                        return Err(BloodError::Runtime(format!(
+                            "Runtime error: repeat count must not be negative, got {}",
+                            n
+                        )));
                     }
-                    if flow_break {
+                    other => {
+                        return Err(BloodError::Runtime(format!(
+                            "Runtime error: repeat count must be an integer, got {}",
+                            other
+                        )));
+                    }
+                };
+
+                self.loop_depth += 1;
+                let mut flow = LoopFlow::Next;
+                for _ in 0..times {
+                    flow = self.run_loop_body(body)?;
+                    if !matches!(flow, LoopFlow::Next) {
                         break;
                     }
                 }
                 self.loop_depth -= 1;
+                if let LoopFlow::Return(v) = flow {
+                    return Ok(ExecutionResult::Return(v));
+                }
+            }
+            Stmt::DoWhile { body, condition } => {
+                self.loop_depth += 1;
+                let flow = loop {
+                    // `continue` skips the rest of the body, but the
+                    // condition is still checked.
+                    match self.run_loop_body(body)? {
+                        LoopFlow::Next => {}
+                        flow => break flow,
+                    }
+                    let cond_val = self.eval_expr(condition)?;
+                    if self.condition(cond_val, "Runtime error: until condition must be boolean")? {
+                        break LoopFlow::Next;
+                    }
+                };
+                self.loop_depth -= 1;
+                if let LoopFlow::Return(v) = flow {
+                    return Ok(ExecutionResult::Return(v));
+                }
+            }
+            Stmt::For {
+                init,
+                condition,
+                step,
+                body,
+            } => {
+                // The scope holding the loop variable is left on every path
+                // out of the loop, including errors.
+                self.enter_scope();
+                let result = self.execute_for(init, condition, step, body);
+                self.exit_scope();
+                return result;
             }
             Stmt::Break => {
                 if self.loop_depth == 0 {
-                    return Err("Runtime error: 'break' used outside of loop".to_string());
+                    return Err(BloodError::Runtime(
+                        "Runtime error: 'break' used outside of loop".to_string(),
+                    ));
                 }
                 return Ok(ExecutionResult::Break);
             }
             Stmt::Continue => {
                 if self.loop_depth == 0 {
-                    return Err("Runtime error: 'continue' used outside of loop".to_string());
+                    return Err(BloodError::Runtime(
+                        "Runtime error: 'continue' used outside of loop".to_string(),
+                    ));
                 }
                 return Ok(ExecutionResult::Continue);
             }
-            Stmt::Fn { name, params, body } => {
+            Stmt::Fn {
+                name,
+                params,
+                return_type,
+                body,
+            } => {
                 let func = Value::Function {
                     name: name.clone(),
-                    params,
-                    body,
+                    params: Rc::new(params.clone()),
+                    return_type: *return_type,
+                    body: Rc::new(body.clone()),
                 };
 
-                self.define_variable(name, func, false)?;
+                self.define_variable(name.clone(), func, false)?;
             }
             Stmt::Return(expr) => {
                 if self.function_depth == 0 {
-                    return Err("Runtime error: 'return' used outside of function".to_string());
+                    return Err(BloodError::Runtime(
+                        "Runtime error: 'return' used outside of function".to_string(),
+                    ));
+                }
+                // A function returning a call to itself doesn't recurse: the
+                // arguments are handed back to `call_value`, which reruns the
+                // body in a fresh frame once this one has unwound.
+                if let Expr::Call(callee, args) = expr
+                    && let Some(current) = &self.current_body
+                    && let Ok(Value::Function { body, .. }) = self.get_variable(callee)
+                    && Rc::ptr_eq(&body, current)
+                {
+                    let mut arg_vals = Vec::new();
+                    for arg in args {
+                        arg_vals.push(self.eval_expr(arg)?);
+                    }
+                    self.tail_call = Some(arg_vals);
+                    return Ok(ExecutionResult::Return(Value::Nil));
                 }
                 let val = self.eval_expr(expr)?;
                 return Ok(ExecutionResult::Return(val));
             }
+            Stmt::Import(path) => self.import(path)?,
         }
         Ok(ExecutionResult::Normal)
     }
 
-    fn eval_expr(&mut self, expr: Expr) -> Result<Value, String> {
+    /// Runs a `for` loop inside the scope already entered for its `init`.
+    fn execute_for(
+        &mut self,
+        init: &[Stmt],
+        condition: &Expr,
+        step: &[Stmt],
+        body: &[Stmt],
+    ) -> Result<ExecutionResult, BloodError> {
+        for stmt in init {
+            self.execute_stmt(stmt)?;
+        }
+        self.loop_depth += 1;
+        let flow = loop {
+            let cond_val = self.eval_expr(condition)?;
+            let cond_bool =
+                self.condition(cond_val, "Runtime error: for condition must be boolean")?;
+            if !cond_bool {
+                break LoopFlow::Next;
+            }
+            match self.run_loop_body(body)? {
+                LoopFlow::Next => {}
+                flow => break flow,
+            }
+            for stmt in step {
+                self.execute_stmt(stmt)?;
+            }
+        };
+        self.loop_depth -= 1;
+        match flow {
+            LoopFlow::Return(v) => Ok(ExecutionResult::Return(v)),
+            _ => Ok(ExecutionResult::Normal),
+        }
+    }
+
+    /// Runs one pass of a loop body in its own scope, which is left again on
+    /// every path out, including errors.
+    fn run_loop_body(&mut self, body: &[Stmt]) -> Result<LoopFlow, BloodError> {
+        self.enter_scope();
+        let mut flow = Ok(LoopFlow::Next);
+        for s in body {
+            match self.execute_stmt(s) {
+                Ok(ExecutionResult::Normal) => {}
+                Ok(ExecutionResult::Continue) => break,
+                Ok(ExecutionResult::Break) => {
+                    flow = Ok(LoopFlow::Break);
+                    break;
+                }
+                Ok(ExecutionResult::Return(v)) => {
+                    flow = Ok(LoopFlow::Return(v));
+                    break;
+                }
+                Err(e) => {
+                    flow = Err(e);
+                    break;
+                }
+            }
+        }
+        self.exit_scope();
+        flow
+    }
+
+    /// Runs the top-level declarations (`let`, `fn` and nested imports) of
+    /// another file, relative to the importing one, into the global scope.
+    /// A file that was already imported is skipped.
+    fn import(&mut self, path: &str) -> Result<(), BloodError> {
+        if self.function_depth > 0 {
+            return Err(BloodError::Runtime(
+                "Runtime Error: 'import' is only allowed at the top level.".to_string(),
+            ));
+        }
+
+        let base = self
+            .import_stack
+            .last()
+            .and_then(|p| p.parent())
+            .unwrap_or(Path::new(""));
+        let full_path = fs::canonicalize(base.join(path)).map_err(|e| {
+            BloodError::Runtime(format!("Runtime Error: Cannot import '{}': {}", path, e))
+        })?;
+        if self.import_stack.contains(&full_path) {
+            return Err(BloodError::Runtime(format!(
+                "Runtime Error: Circular import of '{}'.",
+                path
+            )));
+        }
+        if self.imported.contains(&full_path) {
+            return Ok(());
+        }
+
+        let code = fs::read_to_string(&full_path).map_err(|e| {
+            BloodError::Runtime(format!("Runtime Error: Cannot import '{}': {}", path, e))
+        })?;
+        // Imported files are folded like the main program; folding never
+        // changes what a program does.
+        let program = crate::compile(code, self.newline_terminators).map_err(|e| match e {
+            BloodError::Parse { line, message } => BloodError::Parse {
+                line,
+                message: format!("{} (in '{}')", message, path),
+            },
+            e => e,
+        })?;
+        let program = crate::optimize(program);
+
+        // Functions are hoisted here too, as in `interpret`.
+        self.import_stack.push(full_path.clone());
+        let result = program
+            .iter()
+            .filter(|stmt| matches!(stmt, Stmt::Fn { .. }))
+            .chain(program.iter().filter(|stmt| {
+                matches!(
+                    stmt,
+                    Stmt::Let { .. } | Stmt::LetMulti { .. } | Stmt::Import(_)
+                )
+            }))
+            .try_for_each(|stmt| self.execute_stmt(stmt).map(|_| ()));
+        self.import_stack.pop();
+        result?;
+
+        self.imported.insert(full_path);
+        Ok(())
+    }
+
+    fn eval_expr(&mut self, expr: &Expr) -> Result<Value, BloodError> {
+        self.step()?;
         match expr {
-            Expr::Number(val) => Ok(Value::Integer(val)),
-            Expr::Boolean(val) => Ok(Value::Boolean(val)),
+            Expr::Number(val) => Ok(Value::Integer(*val)),
+            Expr::Float(val) => Ok(Value::Float(*val)),
+            Expr::String(val) => Ok(Value::String(val.clone())),
+            Expr::Boolean(val) => Ok(Value::Boolean(*val)),
             Expr::Nil => Ok(Value::Nil),
-            Expr::Variable(name) => self.get_variable(&name),
+            Expr::Array(elements) => {
+                let mut values = Vec::new();
+                for element in elements {
+                    values.push(self.eval_expr(element)?);
+                }
+                Ok(Value::Array(values))
+            }
+            Expr::Map(entries) => {
+                let mut map = Map::default();
+                for (key, value) in entries {
+                    let key = self.eval_expr(key)?;
+                    check_key(&key)?;
+                    map.insert(key, self.eval_expr(value)?);
+                }
+                Ok(Value::Map(map))
+            }
+            Expr::Variable(name) => self.get_variable(name),
+            Expr::ResolvedVariable { name, depth, slot } => self.get_resolved(name, *depth, *slot),
             Expr::Unary(op, right) => {
-                let r = self.eval_expr(*right)?;
+                let r = self.eval_expr(right)?;
                 match op {
                     Op::Not => match r {
                         Value::Boolean(b) => Ok(Value::Boolean(!b)),
-                        _ => Err("Runtime Error: 'not' expects a boolean.".to_string()),
+                        _ => Err(BloodError::TypeMismatch(
+                            "'not' expects a boolean.".to_string(),
+                        )),
                     },
                     _ => unreachable!("Unary op not implemented"),
                 }
             }
+            // The right side only runs when it is needed.
+            Expr::Binary(left, Op::Coalesce, right) => match self.eval_expr(left)? {
+                Value::Nil => self.eval_expr(right),
+                value => Ok(value),
+            },
+            // With truthy conditions, `and` and `or` short-circuit and yield
+            // the operand that decided the result rather than a boolean.
+            Expr::Binary(left, op @ (Op::And | Op::Or), right) if self.truthy_conditions => {
+                let l = self.eval_expr(left)?;
+                if is_truthy(&l) == (*op == Op::Or) {
+                    Ok(l)
+                } else {
+                    self.eval_expr(right)
+                }
+            }
+            // Otherwise both operands must be booleans, and the right one is
+            // only evaluated when the left one doesn't settle the result.
+            Expr::Binary(left, op @ (Op::And | Op::Or), right) => {
+                let error = || {
+                    BloodError::TypeMismatch(format!(
+                        "'{}' operands must be booleans.",
+                        if *op == Op::And { "and" } else { "or" }
+                    ))
+                };
+                let Value::Boolean(l) = self.eval_expr(left)? else {
+                    return Err(error());
+                };
+                if l == (*op == Op::Or) {
+                    return Ok(Value::Boolean(l));
+                }
+                match self.eval_expr(right)? {
+                    Value::Boolean(r) => Ok(Value::Boolean(r)),
+                    _ => Err(error()),
+                }
+            }
             Expr::Binary(left, op, right) => {
-                let l = self.eval_expr(*left)?;
-                let r = self.eval_expr(*right)?;
+                let l = self.eval_expr(left)?;
+                let r = self.eval_expr(right)?;
+                if let Some(error) = nil_operand_error(op, &l, &r) {
+                    return Err(error);
+                }
 
                 match op {
-                    Op::Add => self.arithmetic(l, r, |a, b| a + b),
-                    Op::Sub => self.arithmetic(l, r, |a, b| a - b),
-                    Op::Mul => self.arithmetic(l, r, |a, b| a * b),
-                    Op::Div => match (l, r) {
-                        (Value::Integer(a), Value::Integer(b)) => {
-                            if b == 0 {
-                                return Err("Runtime Error: Division by zero.".to_string());
-                            }
-                            Ok(Value::Integer(a / b))
+                    Op::Add => match (l, r) {
+                        (Value::Array(mut a), Value::Array(b)) => {
+                            self.check_size(a.len() + b.len())?;
+                            a.extend(b);
+                            Ok(Value::Array(a))
                         }
-                        _ => Err("Runtime Error: Operands must be integers.".to_string()),
+                        (l @ Value::Array(_), r) | (l, r @ Value::Array(_)) => {
+                            Err(BloodError::TypeMismatch(format!(
+                                "Cannot add {} and {}; '+' joins an array only with another array.",
+                                type_name(&l),
+                                type_name(&r)
+                            )))
+                        }
+                        (l, r) => self.arithmetic(l, r, i64::checked_add, |a, b| a + b),
                     },
-                    Op::Mod => match (l, r) {
-                        (Value::Integer(a), Value::Integer(b)) => {
-                            if b == 0 {
-                                return Err("Runtime Error: Modulo by zero.".to_string());
-                            }
-                            Ok(Value::Integer(a % b))
+                    Op::Sub => self.arithmetic(l, r, i64::checked_sub, |a, b| a - b),
+                    Op::Mul => match (l, r) {
+                        (Value::Array(elements), Value::Integer(count))
+                        | (Value::Integer(count), Value::Array(elements)) => {
+                            self.repeat_array(elements, count)
+                        }
+                        (l @ Value::Array(_), r) | (l, r @ Value::Array(_)) => {
+                            Err(BloodError::TypeMismatch(format!(
+                                "Cannot multiply {} and {}; an array can only be repeated an integer number of times.",
+                                type_name(&l),
+                                type_name(&r)
+                            )))
                         }
-                        _ => Err("Runtime Error: Operands must be integers.".to_string()),
+                        (l, r) => self.arithmetic(l, r, i64::checked_mul, |a, b| a * b),
+                    },
+                    Op::Div => match numeric_pair(&l, &r)? {
+                        NumericPair::Ints(_, 0) => Err(BloodError::DivideByZero),
+                        NumericPair::Floats(_, 0.0) => Err(BloodError::DivideByZero),
+                        NumericPair::Ints(a, b) => a
+                            .checked_div(b)
+                            .map(Value::Integer)
+                            .ok_or(BloodError::IntegerOverflow),
+                        NumericPair::Floats(a, b) => Ok(Value::Float(a / b)),
+                    },
+                    Op::Mod => match numeric_pair(&l, &r)? {
+                        NumericPair::Ints(_, 0) => Err(BloodError::ModuloByZero),
+                        NumericPair::Floats(_, 0.0) => Err(BloodError::ModuloByZero),
+                        NumericPair::Ints(a, b) => a
+                            .checked_rem(b)
+                            .map(Value::Integer)
+                            .ok_or(BloodError::IntegerOverflow),
+                        NumericPair::Floats(a, b) => Ok(Value::Float(a % b)),
                     },
 
                     Op::Equal => Ok(Value::Boolean(l == r)),
                     Op::NotEqual => Ok(Value::Boolean(l != r)),
-                    Op::Lt => self.comparison(l, r, |a, b| a < b),
-                    Op::Gt => self.comparison(l, r, |a, b| a > b),
-                    Op::LtEq => self.comparison(l, r, |a, b| a <= b),
-                    Op::GtEq => self.comparison(l, r, |a, b| a >= b),
-
-                    Op::And => match (l, r) {
-                        (Value::Boolean(a), Value::Boolean(b)) => Ok(Value::Boolean(a && b)),
-                        _ => Err("Runtime Error: 'and' operands must be booleans.".to_string()),
+                    Op::Lt => self.comparison(l, r, Ordering::is_lt),
+                    Op::Gt => self.comparison(l, r, Ordering::is_gt),
+                    Op::LtEq => self.comparison(l, r, Ordering::is_le),
+                    Op::GtEq => self.comparison(l, r, Ordering::is_ge),
+
+                    _ => unreachable!("Binary op not implemented"),
+                }
+            }
+            // Stops at the first comparison that fails.
+            Expr::Chain(first, rest) => {
+                let mut left = self.eval_expr(first)?;
+                for (op, operand) in rest {
+                    let right = self.eval_expr(operand)?;
+                    let holds: fn(Ordering) -> bool = match op {
+                        Op::Lt => Ordering::is_lt,
+                        Op::Gt => Ordering::is_gt,
+                        Op::LtEq => Ordering::is_le,
+                        Op::GtEq => Ordering::is_ge,
+                        _ => unreachable!("Chained op not implemented"),
+                    };
+                    if let Some(error) = nil_operand_error(op, &left, &right) {
+                        return Err(error);
+                    }
+                    if self.comparison(left, right.clone(), holds)? == Value::Boolean(false) {
+                        return Ok(Value::Boolean(false));
+                    }
+                    left = right;
+                }
+                Ok(Value::Boolean(true))
+            }
+            Expr::Call(name, args) => self.call_function(name, args, true),
+            Expr::CallValue(callee, args) => self.call_expr(callee, args, true),
+            Expr::Index(collection, index) => {
+                let collection = self.eval_expr(collection)?;
+                let index = self.eval_expr(index)?;
+                index_value(&collection, &index)
+            }
+            Expr::Grouping(inner) => self.eval_expr(inner),
+        }
+    }
+
+    /// Calls a built-in, or else the function value stored under `name`.
+    /// `result_used` is false when the call stands alone as a statement.
+    fn call_function(
+        &mut self,
+        name: &str,
+        args: &[Expr],
+        result_used: bool,
+    ) -> Result<Value, BloodError> {
+        if let Some(result) = self.call_native(name, args) {
+            return result;
+        }
+        let func_val = self.get_variable(name)?;
+        if !matches!(
+            func_val,
+            Value::Function { .. } | Value::Native { .. } | Value::Memoized { .. }
+        ) {
+            return Err(BloodError::NotAFunction(name.to_string()));
+        }
+
+        let mut arg_vals = Vec::new();
+        for arg in args {
+            arg_vals.push(self.eval_expr(arg)?);
+        }
+        self.call_value(&func_val, arg_vals, result_used)
+    }
+
+    /// Calls the function that `callee` evaluates to, as in `handlers[0](x)`.
+    fn call_expr(
+        &mut self,
+        callee: &Expr,
+        args: &[Expr],
+        result_used: bool,
+    ) -> Result<Value, BloodError> {
+        let func_val = self.eval_expr(callee)?;
+        if !matches!(
+            func_val,
+            Value::Function { .. } | Value::Native { .. } | Value::Memoized { .. }
+        ) {
+            return Err(BloodError::NotAFunction(format::expr(callee)));
+        }
+
+        let mut arg_vals = Vec::new();
+        for arg in args {
+            arg_vals.push(self.eval_expr(arg)?);
+        }
+        self.call_value(&func_val, arg_vals, result_used)
+    }
+
+    /// Invokes a function value with already-evaluated arguments.
+    fn call_value(
+        &mut self,
+        func: &Value,
+        args: Vec<Value>,
+        result_used: bool,
+    ) -> Result<Value, BloodError> {
+        let (name, params, return_type, body) = match func {
+            Value::Function {
+                name,
+                params,
+                return_type,
+                body,
+            } => (name, params, return_type, body),
+            Value::Native { func, .. } => {
+                let args = args.iter().map(ValueView::from).collect();
+                return Ok(Value::try_from(func(args)?)?);
+            }
+            Value::Memoized { func, cache, .. } => {
+                let key = args.iter().map(memo_key).collect::<Result<Vec<_>, _>>()?;
+                if let Some(value) = cache.borrow().get(&key) {
+                    return Ok(value.clone());
+                }
+                let value = self.call_value(func, args, result_used)?;
+                cache.borrow_mut().insert(key, value.clone());
+                return Ok(value);
+            }
+            _ => {
+                return Err(BloodError::NotAFunction(func.to_string()));
+            }
+        };
+        let old_body = self.current_body.replace(body.clone());
+        let result = self.run_function(params, body, args);
+        self.current_body = old_body;
+        // Every function an error unwinds through adds a line to its trace.
+        let return_val = result.map_err(|e| e.in_function(name))?;
+
+        let value = match return_val {
+            Some(v) => v,
+            None if self.strict_returns && result_used => {
+                return Err(BloodError::Runtime(format!(
+                    "Runtime Error: Function '{}' did not return a value, but its result was used.",
+                    name
+                )));
+            }
+            None => Value::Nil,
+        };
+        if let Some(ty) = return_type
+            && !type_matches(*ty, &value)
+        {
+            let error = BloodError::TypeMismatch(format!(
+                "Function '{}' should return {}, got {}.",
+                name,
+                ty.name(),
+                type_name(&value)
+            ));
+            return Err(error.in_function(name));
+        }
+        Ok(value)
+    }
+
+    /// Binds `args` to `params` in a new call frame and runs `body`, again for
+    /// each self tail call it makes. Returns the value of the final `return`,
+    /// if any. The frame is popped whether or not the body fails.
+    fn run_function(
+        &mut self,
+        params: &[Param],
+        body: &[Stmt],
+        mut args: Vec<Value>,
+    ) -> Result<Option<Value>, BloodError> {
+        loop {
+            // A trailing rest parameter collects any extra arguments into an array.
+            let (fixed, rest) = match params.split_last() {
+                Some((last, fixed)) if last.rest => (fixed, Some(last)),
+                _ => (params, None),
+            };
+            let required = fixed.iter().filter(|p| p.default.is_none()).count();
+            if args.len() < required || (rest.is_none() && args.len() > fixed.len()) {
+                let expected = if rest.is_some() {
+                    format!("at least {}", required)
+                } else if required == fixed.len() {
+                    fixed.len().to_string()
+                } else {
+                    format!("{} to {}", required, fixed.len())
+                };
+                return Err(BloodError::Arity {
+                    expected,
+                    got: args.len(),
+                });
+            }
+
+            let given = args.len().min(fixed.len());
+            let mut arg_iter = args.into_iter();
+            let mut new_frame = vec![Scope::default()];
+
+            for (param, val) in fixed.iter().zip(arg_iter.by_ref()) {
+                check_param(param, &val)?;
+                self.check_builtin_name(&param.name, &val)?;
+                new_frame[0].insert(
+                    param.name.clone(),
+                    Variable {
+                        value: val,
+                        mutable: false,
                     },
-                    Op::Or => match (l, r) {
-                        (Value::Boolean(a), Value::Boolean(b)) => Ok(Value::Boolean(a || b)),
-                        _ => Err("Runtime Error: 'or' operands must be booleans.".to_string()),
+                );
+            }
+
+            self.call_stack.push(new_frame);
+            self.function_depth += 1;
+            let old_loop_depth = self.loop_depth;
+            self.loop_depth = 0;
+
+            let outcome = self.run_frame(&fixed[given..], rest, arg_iter, body);
+
+            self.loop_depth = old_loop_depth;
+            self.function_depth -= 1;
+            self.call_stack.pop();
+
+            let return_val = outcome?;
+            match self.tail_call.take() {
+                Some(next) => args = next,
+                None => return Ok(return_val),
+            }
+        }
+    }
+
+    /// Fills in the defaulted and rest parameters of the frame just pushed,
+    /// then runs the function body in it.
+    fn run_frame(
+        &mut self,
+        defaulted: &[Param],
+        rest: Option<&Param>,
+        extra_args: std::vec::IntoIter<Value>,
+        body: &[Stmt],
+    ) -> Result<Option<Value>, BloodError> {
+        // Defaults are evaluated inside the new frame, so they can refer to
+        // the parameters before them.
+        for param in defaulted {
+            if let Some(default) = &param.default {
+                let value = self.eval_expr(default)?;
+                check_param(param, &value)?;
+                self.check_builtin_name(&param.name, &value)?;
+                self.current_frame_mut()[0].insert(
+                    param.name.clone(),
+                    Variable {
+                        value,
+                        mutable: false,
                     },
-                    _ => unreachable!("Binary op not implemented"),
+                );
+            }
+        }
+        if let Some(rest) = rest {
+            self.current_frame_mut()[0].insert(
+                rest.name.clone(),
+                Variable {
+                    value: Value::Array(extra_args.collect()),
+                    mutable: false,
+                },
+            );
+        }
+
+        for stmt in body {
+            match self.execute_stmt(stmt)? {
+                ExecutionResult::Return(v) => return Ok(Some(v)),
+                ExecutionResult::Normal => {}
+                _ => {
+                    // Break/Continue should be caught by execute_stmt validation if loop_depth is 0.
                 }
             }
-            Expr::Call(name, args) => {
-                let func_val = self.get_variable(&name)?;
-                match func_val {
-                    Value::Function {
-                        name: _,
-                        params,
-                        body,
-                    } => {
-                        if args.len() != params.len() {
-                            return Err(format!(
-                                "Runtime error: expected {} argument, got {}",
-                                params.len(),
-                                args.len()
-                            ));
-                        }
+        }
+        Ok(None)
+    }
 
-                        let mut arg_vals = Vec::new();
-                        for arg in args {
-                            arg_vals.push(self.eval_expr(arg)?);
-                        }
+    /// Runs the built-in called `name`, or returns `None` when there is no
+    /// such built-in.
+    fn call_native(&mut self, name: &str, args: &[Expr]) -> Option<Result<Value, BloodError>> {
+        let native = *self.builtins.get(name)?;
 
-                        let mut new_frame = vec![HashMap::new()];
+        // remove() changes the collection it is given, so it needs the
+        // variable rather than a copy of its value.
+        if let (
+            "remove",
+            [
+                Expr::Variable(target) | Expr::ResolvedVariable { name: target, .. },
+                key,
+            ],
+        ) = (name, args)
+        {
+            return Some(self.remove_from(target, key));
+        }
 
-                        for (param, val) in params.iter().zip(arg_vals.into_iter()) {
-                            new_frame[0].insert(
-                                param.clone(),
-                                Variable {
-                                    value: val,
-                                    mutable: false,
-                                },
-                            );
-                        }
+        let mut arg_vals = Vec::new();
+        for arg in args {
+            match self.eval_expr(arg) {
+                Ok(val) => arg_vals.push(val),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        Some(native(self, arg_vals))
+    }
 
-                        self.call_stack.push(new_frame);
-                        self.function_depth += 1;
-                        let old_loop_depth = self.loop_depth;
-                        self.loop_depth = 0;
-
-                        let mut return_val = Value::Nil;
-
-                        for stmt in body {
-                            match self.execute_stmt(stmt)? {
-                                ExecutionResult::Return(v) => {
-                                    return_val = v;
-                                    break;
-                                }
-                                ExecutionResult::Normal => {}
-                                _ => {
-                                    // Break/Continue should be caught by execute_stmt validation if loop_depth is 0.
-                                }
-                            }
-                        }
+    /// Takes the entry `key` out of the array or map held in the variable
+    /// `target` and returns it. A map without the key gives `nil`; an array
+    /// index must be in range.
+    fn remove_from(&mut self, target: &str, key: &Expr) -> Result<Value, BloodError> {
+        let mut collection = self.get_variable(target)?;
+        let key = self.eval_expr(key)?;
+        let removed = match (&mut collection, &key) {
+            (Value::Map(map), key) => {
+                check_key(key)?;
+                map.remove(key).unwrap_or(Value::Nil)
+            }
+            (Value::Array(elements), Value::Integer(index)) => {
+                if *index < 0 || *index as usize >= elements.len() {
+                    return Err(BloodError::IndexOutOfBounds {
+                        index: *index,
+                        len: elements.len(),
+                    });
+                }
+                elements.remove(*index as usize)
+            }
+            (Value::Array(_), _) => {
+                return Err(BloodError::Runtime(
+                    "Runtime Error: remove() on an array expects an integer index.".to_string(),
+                ));
+            }
+            _ => return self.native_remove(vec![collection, key]),
+        };
+        self.assign_variable(target, collection)?;
+        Ok(removed)
+    }
 
-                        self.loop_depth = old_loop_depth;
-                        self.function_depth -= 1;
-                        self.call_stack.pop();
+    /// Only reached when `remove` is not given a variable to change.
+    fn native_remove(&mut self, _args: Vec<Value>) -> Result<Value, BloodError> {
+        Err(BloodError::Runtime(
+            "Runtime Error: remove() expects a variable holding an array or a map, and a key."
+                .to_string(),
+        ))
+    }
 
-                        Ok(return_val)
-                    }
-                    _ => Err(format!("Runtime Error: '{}' is not a function.", name)),
+    fn native_error(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        match args.as_slice() {
+            [Value::String(message)] => Err(BloodError::Runtime(message.clone())),
+            _ => Err(BloodError::Runtime(
+                "Runtime Error: error() expects a single string message.".to_string(),
+            )),
+        }
+    }
+
+    fn native_assert_eq(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        let (actual, expected, message) = match args.as_slice() {
+            [actual, expected] => (actual, expected, None),
+            [actual, expected, Value::String(message)] => (actual, expected, Some(message)),
+            _ => {
+                return Err(BloodError::Runtime(
+                    "Runtime Error: assert_eq() expects 2 values and an optional string message."
+                        .to_string(),
+                ));
+            }
+        };
+        if actual == expected {
+            return Ok(Value::Nil);
+        }
+        let (mut expected_text, mut actual_text) = (expected.to_string(), actual.to_string());
+        // `1` and `"1"` print alike, so name the types when the text doesn't differ.
+        if expected_text == actual_text {
+            expected_text = format!("{} ({})", expected_text, type_name(expected));
+            actual_text = format!("{} ({})", actual_text, type_name(actual));
+        }
+        let mut error = format!(
+            "Runtime Error: assertion failed: expected {}, got {}",
+            expected_text, actual_text
+        );
+        if let Some(message) = message {
+            error = format!("{} ({})", error, message);
+        }
+        Err(BloodError::Runtime(error))
+    }
+
+    fn native_exit(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        let code = match args.as_slice() {
+            [] => 0,
+            [Value::Integer(code)] => *code as i32,
+            _ => {
+                return Err(BloodError::Runtime(
+                    "Runtime Error: exit() expects an optional integer exit code.".to_string(),
+                ));
+            }
+        };
+        // The host is likely to end the process as soon as it sees the code,
+        // before the interpreter and its output are dropped.
+        let _ = self.out.flush();
+        Err(BloodError::Exit(code))
+    }
+
+    fn native_clock(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        if !args.is_empty() {
+            return Err(BloodError::Runtime(
+                "Runtime Error: clock() takes no arguments.".to_string(),
+            ));
+        }
+        Ok(Value::Integer(self.started.elapsed().as_millis() as i64))
+    }
+
+    fn native_random(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        let (lo, hi) = match args.as_slice() {
+            [Value::Integer(lo), Value::Integer(hi)] if lo < hi => (*lo, *hi),
+            [Value::Integer(_), Value::Integer(_)] => {
+                return Err(BloodError::Runtime(
+                    "Runtime Error: random() expects lo to be less than hi.".to_string(),
+                ));
+            }
+            _ => {
+                return Err(BloodError::Runtime(
+                    "Runtime Error: random() expects 2 integers.".to_string(),
+                ));
+            }
+        };
+        let span = (hi as i128 - lo as i128) as u64;
+        let offset = self.next_random() % span;
+        Ok(Value::Integer((lo as i128 + offset as i128) as i64))
+    }
+
+    fn native_seed(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        match args.as_slice() {
+            [Value::Integer(n)] => {
+                self.rng_state = nonzero_seed(*n as u64);
+                Ok(Value::Nil)
+            }
+            _ => Err(BloodError::Runtime(
+                "Runtime Error: seed() expects an integer.".to_string(),
+            )),
+        }
+    }
+
+    fn next_random(&mut self) -> u64 {
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng_state = x;
+        x
+    }
+
+    fn native_write(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        for arg in &args {
+            let text = self.format_value(arg);
+            write!(self.out, "{}", text).map_err(output_error)?;
+        }
+        self.out.flush().map_err(output_error)?;
+        Ok(Value::Nil)
+    }
+
+    fn native_eprint(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        let parts: Vec<String> = args.iter().map(Value::to_string).collect();
+        eprintln!("{}", parts.join(" "));
+        Ok(Value::Nil)
+    }
+
+    /// Prints a value and its type to stderr, then hands the value back so a
+    /// call can wrap any expression.
+    fn native_debug(&mut self, mut args: Vec<Value>) -> Result<Value, BloodError> {
+        if args.len() != 1 {
+            return Err(BloodError::Runtime(
+                "Runtime Error: debug() expects 1 argument.".to_string(),
+            ));
+        }
+        let value = args.remove(0);
+        eprintln!("{}: {}", type_name(&value), value);
+        Ok(value)
+    }
+
+    fn native_pretty_print(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        match args.as_slice() {
+            [value] => {
+                let text = self.pretty(value, 0);
+                self.print_line(&text)?;
+                Ok(Value::Nil)
+            }
+            _ => Err(BloodError::Runtime(
+                "Runtime Error: pretty_print() expects 1 argument.".to_string(),
+            )),
+        }
+    }
+
+    /// Substitutes each `{}` in the template with the next argument. `{{` and
+    /// `}}` stand for literal braces.
+    fn native_format(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        let (template, values) = match args.split_first() {
+            Some((Value::String(template), values)) => (template, values),
+            _ => {
+                return Err(BloodError::Runtime(
+                    "Runtime Error: format() expects a format string as its first argument."
+                        .to_string(),
+                ));
+            }
+        };
+
+        let mut out = String::new();
+        let mut values = values.iter();
+        let mut chars = template.chars().peekable();
+        while let Some(ch) = chars.next() {
+            match (ch, chars.peek()) {
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    chars.next();
+                    out.push(ch);
+                }
+                ('{', Some('}')) => {
+                    chars.next();
+                    let value = values.next().ok_or_else(|| {
+                        "Runtime Error: format() has more placeholders than arguments.".to_string()
+                    })?;
+                    out.push_str(&value.to_string());
+                    self.check_size(out.len())?;
+                }
+                ('{', _) | ('}', _) => {
+                    return Err(BloodError::Runtime(format!(
+                        "Runtime Error: format() found an unmatched '{}'; use '{}{}' for a literal brace.",
+                        ch, ch, ch
+                    )));
                 }
+                _ => out.push(ch),
+            }
+        }
+        if values.next().is_some() {
+            return Err(BloodError::Runtime(
+                "Runtime Error: format() has more arguments than placeholders.".to_string(),
+            ));
+        }
+        Ok(Value::String(out))
+    }
+
+    fn native_sqrt(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        let x = match args.as_slice() {
+            [arg] => number_arg("sqrt", arg)?,
+            _ => {
+                return Err(BloodError::Runtime(
+                    "Runtime Error: sqrt() expects 1 argument.".to_string(),
+                ));
+            }
+        };
+        if x < 0.0 {
+            return Err(BloodError::Runtime(
+                "Runtime Error: sqrt() of a negative number.".to_string(),
+            ));
+        }
+        Ok(Value::Float(x.sqrt()))
+    }
+
+    fn native_floor(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        round_with("floor", args, f64::floor)
+    }
+
+    fn native_ceil(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        round_with("ceil", args, f64::ceil)
+    }
+
+    fn native_round(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        round_with("round", args, f64::round)
+    }
+
+    fn native_pow(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        match args.as_slice() {
+            [Value::Integer(base), Value::Integer(exp)] if *exp >= 0 => u32::try_from(*exp)
+                .ok()
+                .and_then(|exp| base.checked_pow(exp))
+                .map(Value::Integer)
+                .ok_or(BloodError::IntegerOverflow),
+            [base, exp] => Ok(Value::Float(
+                number_arg("pow", base)?.powf(number_arg("pow", exp)?),
+            )),
+            _ => Err(BloodError::Runtime(
+                "Runtime Error: pow() expects 2 arguments.".to_string(),
+            )),
+        }
+    }
+
+    fn native_clone(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        // Value::clone copies arrays all the way down.
+        match args.as_slice() {
+            [value] => Ok(value.clone()),
+            _ => Err(BloodError::Runtime(
+                "Runtime Error: clone() expects 1 argument.".to_string(),
+            )),
+        }
+    }
+
+    fn native_divmod(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        let (a, b) = match args.as_slice() {
+            [Value::Integer(_), Value::Integer(0)] => {
+                return Err(BloodError::DivideByZero);
+            }
+            [Value::Integer(a), Value::Integer(b)] => (*a, *b),
+            _ => {
+                return Err(BloodError::Runtime(
+                    "Runtime Error: divmod() expects 2 integers.".to_string(),
+                ));
+            }
+        };
+        match (a.checked_div(b), a.checked_rem(b)) {
+            (Some(q), Some(r)) => Ok(Value::Array(vec![Value::Integer(q), Value::Integer(r)])),
+            _ => Err(BloodError::IntegerOverflow),
+        }
+    }
+
+    fn native_min(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        extreme("min", args, Ordering::Less)
+    }
+
+    fn native_max(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        extreme("max", args, Ordering::Greater)
+    }
+
+    fn native_sum(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        fold_numbers("sum", args, 0, i64::checked_add, |a, b| a + b)
+    }
+
+    fn native_product(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        fold_numbers("product", args, 1, i64::checked_mul, |a, b| a * b)
+    }
+
+    fn native_sort(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        let (mut elements, descending) = match args.as_slice() {
+            [Value::Array(elements)] => (elements.clone(), false),
+            [Value::Array(elements), Value::Boolean(descending)] => (elements.clone(), *descending),
+            _ => {
+                return Err(BloodError::Runtime(
+                    "Runtime Error: sort() expects an array and an optional boolean.".to_string(),
+                ));
+            }
+        };
+        if let Some(first) = elements.first()
+            && !elements.iter().all(|e| compare_values(first, e).is_some())
+        {
+            return Err(BloodError::Runtime(
+                "Runtime Error: sort() expects all numbers or all strings.".to_string(),
+            ));
+        }
+
+        elements.sort_by(|a, b| compare_values(a, b).unwrap());
+        if descending {
+            elements.reverse();
+        }
+        Ok(Value::Array(elements))
+    }
+
+    fn native_contains(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        match args.as_slice() {
+            [Value::Array(elements), needle] => Ok(Value::Boolean(elements.contains(needle))),
+            [Value::Map(map), key] => Ok(Value::Boolean(map.get(key).is_some())),
+            [Value::String(haystack), Value::String(needle)] => {
+                Ok(Value::Boolean(haystack.contains(needle.as_str())))
+            }
+            [Value::String(_), _] => Err(BloodError::Runtime(
+                "Runtime Error: contains() on a string expects a string to search for.".to_string(),
+            )),
+            [_, _] => Err(BloodError::Runtime(
+                "Runtime Error: contains() expects an array, a map or a string.".to_string(),
+            )),
+            _ => Err(BloodError::Runtime(
+                "Runtime Error: contains() expects 2 arguments.".to_string(),
+            )),
+        }
+    }
+
+    /// Position of the first match, counted in elements for arrays and in
+    /// characters for strings, or `nil` if there is none.
+    fn native_index_of(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        let index = match args.as_slice() {
+            [Value::Array(elements), needle] => elements.iter().position(|e| e == needle),
+            [Value::String(haystack), Value::String(needle)] => haystack
+                .find(needle.as_str())
+                .map(|byte| haystack[..byte].chars().count()),
+            [Value::String(_), _] => {
+                return Err(BloodError::Runtime(
+                    "Runtime Error: index_of() on a string expects a string to search for."
+                        .to_string(),
+                ));
+            }
+            _ => {
+                return Err(BloodError::Runtime(
+                    "Runtime Error: index_of() expects an array or a string and a value."
+                        .to_string(),
+                ));
+            }
+        };
+        Ok(index.map_or(Value::Nil, |i| Value::Integer(i as i64)))
+    }
+
+    /// The elements in `[start, end)`. Bounds outside the array are errors
+    /// rather than being clamped.
+    fn native_slice(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        let (elements, start, end) = match args.as_slice() {
+            [
+                Value::Array(elements),
+                Value::Integer(start),
+                Value::Integer(end),
+            ] => (elements, *start, *end),
+            _ => {
+                return Err(BloodError::Runtime(
+                    "Runtime Error: slice() expects an array and two integer indices.".to_string(),
+                ));
             }
+        };
+        if start < 0 || end < start || end as usize > elements.len() {
+            return Err(BloodError::Runtime(format!(
+                "Runtime Error: slice() range {}..{} is out of bounds for an array of length {}.",
+                start,
+                end,
+                elements.len()
+            )));
         }
+        Ok(Value::Array(
+            elements[start as usize..end as usize].to_vec(),
+        ))
     }
 
-    fn arithmetic<F>(&self, l: Value, r: Value, op: F) -> Result<Value, String>
+    fn native_reverse(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        match args.as_slice() {
+            [Value::Array(elements)] => Ok(Value::Array(elements.iter().rev().cloned().collect())),
+            [Value::String(text)] => Ok(Value::String(text.chars().rev().collect())),
+            _ => Err(BloodError::Runtime(
+                "Runtime Error: reverse() expects an array or a string.".to_string(),
+            )),
+        }
+    }
+
+    fn native_zip(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        match args.as_slice() {
+            [Value::Array(xs), Value::Array(ys)] => Ok(Value::Array(
+                xs.iter()
+                    .zip(ys)
+                    .map(|(x, y)| Value::Array(vec![x.clone(), y.clone()]))
+                    .collect(),
+            )),
+            _ => Err(BloodError::Runtime(
+                "Runtime Error: zip() expects 2 arrays.".to_string(),
+            )),
+        }
+    }
+
+    fn native_enumerate(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        match args.as_slice() {
+            [Value::Array(elements)] => Ok(Value::Array(
+                elements
+                    .iter()
+                    .enumerate()
+                    .map(|(i, e)| Value::Array(vec![Value::Integer(i as i64), e.clone()]))
+                    .collect(),
+            )),
+            _ => Err(BloodError::Runtime(
+                "Runtime Error: enumerate() expects an array.".to_string(),
+            )),
+        }
+    }
+
+    fn native_keys(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        match args.as_slice() {
+            [Value::Map(map)] => Ok(Value::Array(
+                map.entries.iter().map(|(k, _)| k.clone()).collect(),
+            )),
+            _ => Err(BloodError::Runtime(
+                "Runtime Error: keys() expects a map.".to_string(),
+            )),
+        }
+    }
+
+    fn native_values(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        match args.as_slice() {
+            [Value::Map(map)] => Ok(Value::Array(
+                map.entries.iter().map(|(_, v)| v.clone()).collect(),
+            )),
+            _ => Err(BloodError::Runtime(
+                "Runtime Error: values() expects a map.".to_string(),
+            )),
+        }
+    }
+
+    fn native_get(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        let (map, key, default) = match args.as_slice() {
+            [Value::Map(map), key] => (map, key, Value::Nil),
+            [Value::Map(map), key, default] => (map, key, default.clone()),
+            _ => {
+                return Err(BloodError::Runtime(
+                    "Runtime Error: get() expects a map, a key and an optional default."
+                        .to_string(),
+                ));
+            }
+        };
+        check_key(key)?;
+        Ok(map.get(key).cloned().unwrap_or(default))
+    }
+
+    /// A copy of the map with `key` set. Updating a key keeps its place in
+    /// the order; a new key goes last.
+    fn native_set(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        let (mut map, key, value) = match args.as_slice() {
+            [Value::Map(map), key, value] => (map.clone(), key.clone(), value.clone()),
+            _ => {
+                return Err(BloodError::Runtime(
+                    "Runtime Error: set() expects a map, a key and a value.".to_string(),
+                ));
+            }
+        };
+        check_key(&key)?;
+        if map.get(&key).is_none() {
+            self.check_size(map.entries.len() + 1)?;
+        }
+        map.insert(key, value);
+        Ok(Value::Map(map))
+    }
+
+    fn native_ord(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        let text = match args.as_slice() {
+            [Value::String(text)] => text,
+            _ => {
+                return Err(BloodError::Runtime(
+                    "Runtime Error: ord() expects a string.".to_string(),
+                ));
+            }
+        };
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (Some(ch), None) => Ok(Value::Integer(ch as i64)),
+            _ => Err(BloodError::Runtime(format!(
+                "Runtime Error: ord() expects a single character, got \"{}\".",
+                text
+            ))),
+        }
+    }
+
+    fn native_chr(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        let code = match args.as_slice() {
+            [Value::Integer(code)] => *code,
+            _ => {
+                return Err(BloodError::Runtime(
+                    "Runtime Error: chr() expects an integer.".to_string(),
+                ));
+            }
+        };
+        match u32::try_from(code).ok().and_then(char::from_u32) {
+            Some(ch) => Ok(Value::String(ch.to_string())),
+            None => Err(BloodError::Runtime(format!(
+                "Runtime Error: chr() got {}, which is not a valid Unicode code point.",
+                code
+            ))),
+        }
+    }
+
+    fn native_join(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        let (elements, separator) = match args.as_slice() {
+            [Value::Array(elements), Value::String(separator)] => (elements, separator),
+            _ => {
+                return Err(BloodError::Runtime(
+                    "Runtime Error: join() expects an array and a string separator.".to_string(),
+                ));
+            }
+        };
+        let mut parts = Vec::new();
+        for element in elements {
+            match element {
+                Value::String(part) => parts.push(part.as_str()),
+                other => {
+                    return Err(BloodError::Runtime(format!(
+                        "Runtime Error: join() expects an array of strings, found {}.",
+                        other
+                    )));
+                }
+            }
+        }
+        let joined_len = parts.iter().map(|p| p.len()).sum::<usize>()
+            + separator.len() * parts.len().saturating_sub(1);
+        self.check_size(joined_len)?;
+        Ok(Value::String(parts.join(separator)))
+    }
+
+    fn native_replace(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        match args.as_slice() {
+            [Value::String(_), Value::String(from), Value::String(_)] if from.is_empty() => {
+                Err(BloodError::Runtime(
+                    "Runtime Error: replace() cannot replace an empty string.".to_string(),
+                ))
+            }
+            [Value::String(s), Value::String(from), Value::String(to)] => {
+                let matches = s.matches(from.as_str()).count();
+                self.check_size(s.len() - matches * from.len() + matches * to.len())?;
+                Ok(Value::String(s.replace(from.as_str(), to)))
+            }
+            _ => Err(BloodError::Runtime(
+                "Runtime Error: replace() expects 3 strings.".to_string(),
+            )),
+        }
+    }
+
+    fn native_trim(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        trim_with("trim", args, str::trim)
+    }
+
+    fn native_trim_start(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        trim_with("trim_start", args, str::trim_start)
+    }
+
+    fn native_trim_end(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        trim_with("trim_end", args, str::trim_end)
+    }
+
+    fn native_all(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        let flags = bool_elements("all", &args)?;
+        Ok(Value::Boolean(flags.iter().all(|&b| b)))
+    }
+
+    fn native_any(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        let flags = bool_elements("any", &args)?;
+        Ok(Value::Boolean(flags.iter().any(|&b| b)))
+    }
+
+    fn native_bool(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        match args.as_slice() {
+            [value] => Ok(Value::Boolean(is_truthy(value))),
+            _ => Err(BloodError::Runtime(
+                "Runtime Error: bool() expects 1 argument.".to_string(),
+            )),
+        }
+    }
+
+    fn native_memoize(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        match args.as_slice() {
+            [func @ (Value::Function { name, .. } | Value::Native { name, .. })] => {
+                Ok(Value::Memoized {
+                    name: name.clone(),
+                    func: Rc::new(func.clone()),
+                    cache: Rc::new(RefCell::new(HashMap::new())),
+                })
+            }
+            _ => Err(BloodError::Runtime(
+                "Runtime Error: memoize() expects a function.".to_string(),
+            )),
+        }
+    }
+
+    fn native_map(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        let (elements, func) = match args.as_slice() {
+            [
+                Value::Array(elements),
+                func @ (Value::Function { .. } | Value::Native { .. } | Value::Memoized { .. }),
+            ] => (elements, func),
+            _ => {
+                return Err(BloodError::Runtime(
+                    "Runtime Error: map() expects an array and a function.".to_string(),
+                ));
+            }
+        };
+        let mut mapped = Vec::new();
+        for element in elements {
+            mapped.push(self.call_value(func, vec![element.clone()], true)?);
+        }
+        Ok(Value::Array(mapped))
+    }
+
+    fn native_filter(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        let (elements, func) = match args.as_slice() {
+            [
+                Value::Array(elements),
+                func @ (Value::Function { .. } | Value::Native { .. } | Value::Memoized { .. }),
+            ] => (elements, func),
+            _ => {
+                return Err(BloodError::Runtime(
+                    "Runtime Error: filter() expects an array and a function.".to_string(),
+                ));
+            }
+        };
+        let mut kept = Vec::new();
+        for element in elements {
+            match self.call_value(func, vec![element.clone()], true)? {
+                Value::Boolean(true) => kept.push(element.clone()),
+                Value::Boolean(false) => {}
+                _ => {
+                    return Err(BloodError::Runtime(
+                        "Runtime Error: filter() predicate must return a boolean.".to_string(),
+                    ));
+                }
+            }
+        }
+        Ok(Value::Array(kept))
+    }
+
+    fn native_reduce(&mut self, args: Vec<Value>) -> Result<Value, BloodError> {
+        let (elements, init, func) = match args.as_slice() {
+            [
+                Value::Array(elements),
+                init,
+                func @ Value::Function { params, .. },
+            ] => {
+                if params.len() != 2 {
+                    return Err(BloodError::Runtime(
+                        "Runtime Error: reduce() expects a function taking 2 arguments."
+                            .to_string(),
+                    ));
+                }
+                (elements, init, func)
+            }
+            [
+                Value::Array(elements),
+                init,
+                func @ (Value::Native { .. } | Value::Memoized { .. }),
+            ] => (elements, init, func),
+            _ => {
+                return Err(BloodError::Runtime(
+                    "Runtime Error: reduce() expects an array, an initial value and a function."
+                        .to_string(),
+                ));
+            }
+        };
+        let mut acc = init.clone();
+        for element in elements {
+            acc = self.call_value(func, vec![acc, element.clone()], true)?;
+        }
+        Ok(acc)
+    }
+
+    /// `elements` repeated `count` times, for `array * integer`.
+    fn repeat_array(&self, elements: Vec<Value>, count: i64) -> Result<Value, BloodError> {
+        if count < 0 {
+            return Err(BloodError::Runtime(format!(
+                "Runtime Error: Cannot repeat an array a negative number of times ({}).",
+                count
+            )));
+        }
+        let total = usize::try_from(count)
+            .ok()
+            .and_then(|n| n.checked_mul(elements.len()))
+            .unwrap_or(usize::MAX);
+        self.check_size(total)?;
+        Ok(Value::Array(
+            elements.iter().cycle().take(total).cloned().collect(),
+        ))
+    }
+
+    /// Applies `int_op` to two integers, where `None` means the result
+    /// overflowed, or `float_op` once either side is a float.
+    fn arithmetic<F, G>(
+        &self,
+        l: Value,
+        r: Value,
+        int_op: F,
+        float_op: G,
+    ) -> Result<Value, BloodError>
     where
-        F: Fn(i64, i64) -> i64,
+        F: Fn(i64, i64) -> Option<i64>,
+        G: Fn(f64, f64) -> f64,
     {
-        match (l, r) {
-            (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(op(a, b))),
-            _ => Err("Runtime Error: Operands must be integers.".to_string()),
+        match numeric_pair(&l, &r)? {
+            NumericPair::Ints(a, b) => int_op(a, b)
+                .map(Value::Integer)
+                .ok_or(BloodError::IntegerOverflow),
+            NumericPair::Floats(a, b) => Ok(Value::Float(float_op(a, b))),
         }
     }
 
-    fn comparison<F>(&self, l: Value, r: Value, op: F) -> Result<Value, String>
+    fn comparison<F>(&self, l: Value, r: Value, op: F) -> Result<Value, BloodError>
     where
-        F: Fn(i64, i64) -> bool,
+        F: Fn(Ordering) -> bool,
     {
-        match (l, r) {
-            (Value::Integer(a), Value::Integer(b)) => Ok(Value::Boolean(op(a, b))),
-            _ => Err("Runtime Error: Comparison operands must be integers.".to_string()),
+        match compare_values(&l, &r) {
+            Some(ordering) => Ok(Value::Boolean(op(ordering))),
+            None => Err(BloodError::TypeMismatch(
+                "Comparison operands must both be numbers or both be strings.".to_string(),
+            )),
+        }
+    }
+}
+
+/// `false`, `nil`, zero, the empty string and the empty array are falsy;
+/// every other value is truthy.
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Boolean(b) => *b,
+        Value::Nil => false,
+        Value::Integer(v) => *v != 0,
+        Value::Float(v) => *v != 0.0,
+        Value::String(v) => !v.is_empty(),
+        Value::Array(v) => !v.is_empty(),
+        Value::Map(map) => !map.entries.is_empty(),
+        Value::Function { .. } | Value::Native { .. } | Value::Memoized { .. } => true,
+    }
+}
+
+/// Orders two numbers or two strings. Returns `None` for any other
+/// combination, and when a float comparison involves `NaN`.
+fn compare_values(l: &Value, r: &Value) -> Option<Ordering> {
+    if let (Value::String(a), Value::String(b)) = (l, r) {
+        return Some(a.cmp(b));
+    }
+    match numeric_pair(l, r).ok()? {
+        NumericPair::Ints(a, b) => Some(a.cmp(&b)),
+        NumericPair::Floats(a, b) => a.partial_cmp(&b),
+    }
+}
+
+/// Numeric operands after promotion. Two integers stay integers; if either
+/// side is a float, both become floats.
+enum NumericPair {
+    Ints(i64, i64),
+    Floats(f64, f64),
+}
+
+fn numeric_pair(l: &Value, r: &Value) -> Result<NumericPair, BloodError> {
+    match (l, r) {
+        (Value::Integer(a), Value::Integer(b)) => Ok(NumericPair::Ints(*a, *b)),
+        (Value::Integer(a), Value::Float(b)) => Ok(NumericPair::Floats(*a as f64, *b)),
+        (Value::Float(a), Value::Integer(b)) => Ok(NumericPair::Floats(*a, *b as f64)),
+        (Value::Float(a), Value::Float(b)) => Ok(NumericPair::Floats(*a, *b)),
+        _ => Err(BloodError::TypeMismatch(
+            "Operands must be numbers.".to_string(),
+        )),
+    }
+}
+
+/// Explains an arithmetic or comparison on `nil`, which usually comes from
+/// the result of a function that finished without `return`. Returns `None`
+/// when neither operand is `nil` or `op` accepts `nil`.
+fn nil_operand_error(op: &Op, l: &Value, r: &Value) -> Option<BloodError> {
+    let other = match (l, r) {
+        (Value::Nil, other) | (other, Value::Nil) => other,
+        _ => return None,
+    };
+    let action = match op {
+        Op::Add => "add",
+        Op::Sub => "subtract",
+        Op::Mul => "multiply",
+        Op::Div => "divide",
+        Op::Mod => "take the remainder of",
+        Op::Lt | Op::Gt | Op::LtEq | Op::GtEq => "compare",
+        _ => return None,
+    };
+    Some(BloodError::TypeMismatch(format!(
+        "Cannot {} nil and {}. Did a function forget to return a value?",
+        action,
+        type_name(other)
+    )))
+}
+
+/// Whether `value` fits a type annotation.
+fn type_matches(ty: TypeTag, value: &Value) -> bool {
+    match ty {
+        TypeTag::Number => matches!(value, Value::Integer(_) | Value::Float(_)),
+        _ => type_name(value) == ty.name(),
+    }
+}
+
+/// Fails if a parameter's annotation doesn't fit the value bound to it.
+fn check_param(param: &Param, value: &Value) -> Result<(), BloodError> {
+    match param.ty {
+        Some(ty) if !type_matches(ty, value) => Err(BloodError::TypeMismatch(format!(
+            "Parameter '{}' expects {}, got {}.",
+            param.name,
+            ty.name(),
+            type_name(value)
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Reads `collection[index]`: an array element by position, or a map value
+/// by key. A missing map key gives `nil`, like `get`.
+fn index_value(collection: &Value, index: &Value) -> Result<Value, BloodError> {
+    match (collection, index) {
+        (Value::Array(elements), Value::Integer(i)) => usize::try_from(*i)
+            .ok()
+            .and_then(|i| elements.get(i))
+            .cloned()
+            .ok_or(BloodError::IndexOutOfBounds {
+                index: *i,
+                len: elements.len(),
+            }),
+        (Value::Array(_), _) => Err(BloodError::TypeMismatch(format!(
+            "Array index must be an integer, got {}.",
+            type_name(index)
+        ))),
+        (Value::Map(map), key) => {
+            check_key(key)?;
+            Ok(map.get(key).cloned().unwrap_or(Value::Nil))
+        }
+        _ => Err(BloodError::TypeMismatch(format!(
+            "Cannot index {}; only arrays and maps can be indexed.",
+            type_name(collection)
+        ))),
+    }
+}
+
+fn output_error(error: io::Error) -> BloodError {
+    BloodError::Runtime(format!("Runtime Error: {}", error))
+}
+
+fn is_collection(value: &Value) -> bool {
+    matches!(value, Value::Array(_) | Value::Map(_))
+}
+
+/// Fails unless `key` can be used as a map key: only strings and integers
+/// can.
+fn check_key(key: &Value) -> Result<(), BloodError> {
+    match key {
+        Value::String(_) | Value::Integer(_) => Ok(()),
+        _ => Err(BloodError::TypeMismatch(format!(
+            "Map keys must be strings or integers, got {}.",
+            type_name(key)
+        ))),
+    }
+}
+
+/// The name `debug` reports for a value's type.
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Integer(_) => "integer",
+        Value::Float(_) => "float",
+        Value::String(_) => "string",
+        Value::Boolean(_) => "bool",
+        Value::Nil => "nil",
+        Value::Array(_) => "array",
+        Value::Map(_) => "map",
+        Value::Function { .. } | Value::Native { .. } | Value::Memoized { .. } => "function",
+    }
+}
+
+/// Builds the cache key for one argument of a memoized function.
+fn memo_key(value: &Value) -> Result<MemoKey, BloodError> {
+    Ok(match value {
+        Value::Integer(v) => MemoKey::Integer(*v),
+        Value::Float(v) => MemoKey::Float(v.to_bits()),
+        Value::String(v) => MemoKey::String(v.clone()),
+        Value::Boolean(v) => MemoKey::Boolean(*v),
+        Value::Nil => MemoKey::Nil,
+        Value::Array(elements) => {
+            MemoKey::Array(elements.iter().map(memo_key).collect::<Result<_, _>>()?)
+        }
+        Value::Map(map) => MemoKey::Map(
+            map.entries
+                .iter()
+                .map(|(k, v)| Ok((memo_key(k)?, memo_key(v)?)))
+                .collect::<Result<_, BloodError>>()?,
+        ),
+        _ => {
+            return Err(BloodError::Runtime(format!(
+                "Runtime Error: Cannot use {} as an argument to a memoized function.",
+                value
+            )));
+        }
+    })
+}
+
+/// Reads a numeric built-in argument, promoting integers to floats.
+fn number_arg(name: &str, value: &Value) -> Result<f64, BloodError> {
+    match value {
+        Value::Integer(v) => Ok(*v as f64),
+        Value::Float(v) => Ok(*v),
+        _ => Err(BloodError::Runtime(format!(
+            "Runtime Error: {}() expects a number.",
+            name
+        ))),
+    }
+}
+
+/// Seeds random() from the clock so separate runs differ.
+fn time_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    nonzero_seed(nanos)
+}
+
+/// xorshift gets stuck at zero, so a zero seed is swapped for a fixed constant.
+fn nonzero_seed(seed: u64) -> u64 {
+    if seed == 0 {
+        0x9E37_79B9_7F4A_7C15
+    } else {
+        seed
+    }
+}
+
+/// Shared body of `trim`, `trim_start` and `trim_end`.
+fn trim_with(name: &str, args: Vec<Value>, op: fn(&str) -> &str) -> Result<Value, BloodError> {
+    match args.as_slice() {
+        [Value::String(s)] => Ok(Value::String(op(s).to_string())),
+        _ => Err(BloodError::Runtime(format!(
+            "Runtime Error: {}() expects a string.",
+            name
+        ))),
+    }
+}
+
+/// Shared body of the `is_*` type predicates, which take exactly one value.
+fn type_check(
+    name: &str,
+    args: Vec<Value>,
+    is_type: fn(&Value) -> bool,
+) -> Result<Value, BloodError> {
+    match args.as_slice() {
+        [value] => Ok(Value::Boolean(is_type(value))),
+        _ => Err(BloodError::Runtime(format!(
+            "Runtime Error: {}() expects 1 argument.",
+            name
+        ))),
+    }
+}
+
+/// Shared body of `min` and `max`: picks the value ordered `wanted` relative
+/// to all the others, from the arguments or from a single array argument.
+/// Ties go to the earliest value.
+fn extreme(name: &str, args: Vec<Value>, wanted: Ordering) -> Result<Value, BloodError> {
+    let values = if let [Value::Array(elements)] = args.as_slice() {
+        elements.clone()
+    } else {
+        args
+    };
+    let mut values = values.into_iter();
+    let mut best = values.next().ok_or_else(|| {
+        BloodError::Runtime(format!(
+            "Runtime Error: {}() expects a non-empty array or at least one value.",
+            name
+        ))
+    })?;
+    for value in values {
+        match compare_values(&value, &best) {
+            Some(ordering) if ordering == wanted => best = value,
+            Some(_) => {}
+            None => {
+                return Err(BloodError::Runtime(format!(
+                    "Runtime Error: {}() expects all numbers or all strings.",
+                    name
+                )));
+            }
+        }
+    }
+    Ok(best)
+}
+
+/// Shared body of `sum` and `product`, folding a single array of numbers
+/// from `init`. The result stays an integer, with overflow reported as an
+/// error, until a float element turns it into a float.
+fn fold_numbers(
+    name: &str,
+    args: Vec<Value>,
+    init: i64,
+    int_op: fn(i64, i64) -> Option<i64>,
+    float_op: fn(f64, f64) -> f64,
+) -> Result<Value, BloodError> {
+    let expects = || {
+        BloodError::Runtime(format!(
+            "Runtime Error: {}() expects an array of numbers.",
+            name
+        ))
+    };
+    let elements = match args.as_slice() {
+        [Value::Array(elements)] => elements,
+        _ => return Err(expects()),
+    };
+    let mut total = Value::Integer(init);
+    for element in elements {
+        total = match numeric_pair(&total, element).map_err(|_| expects())? {
+            NumericPair::Ints(a, b) => {
+                Value::Integer(int_op(a, b).ok_or(BloodError::IntegerOverflow)?)
+            }
+            NumericPair::Floats(a, b) => Value::Float(float_op(a, b)),
+        };
+    }
+    Ok(total)
+}
+
+/// Unpacks the single array-of-booleans argument taken by `all` and `any`.
+fn bool_elements(name: &str, args: &[Value]) -> Result<Vec<bool>, BloodError> {
+    let elements = match args {
+        [Value::Array(elements)] => elements,
+        _ => {
+            return Err(BloodError::Runtime(format!(
+                "Runtime Error: {}() expects an array.",
+                name
+            )));
+        }
+    };
+    elements
+        .iter()
+        .map(|element| match element {
+            Value::Boolean(b) => Ok(*b),
+            other => Err(BloodError::Runtime(format!(
+                "Runtime Error: {}() expects an array of booleans, found {}.",
+                name, other
+            ))),
+        })
+        .collect()
+}
+
+/// Shared body of `floor`, `ceil` and `round`, which all produce an integer.
+fn round_with(name: &str, args: Vec<Value>, op: fn(f64) -> f64) -> Result<Value, BloodError> {
+    match args.as_slice() {
+        [Value::Integer(v)] => Ok(Value::Integer(*v)),
+        [arg] => {
+            let rounded = op(number_arg(name, arg)?);
+            if rounded.is_finite() && rounded.abs() < i64::MAX as f64 {
+                Ok(Value::Integer(rounded as i64))
+            } else {
+                Err(BloodError::Runtime(format!(
+                    "Runtime Error: {}() result does not fit in an integer.",
+                    name
+                )))
+            }
+        }
+        _ => Err(BloodError::Runtime(format!(
+            "Runtime Error: {}() expects 1 argument.",
+            name
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `src` with the default options and returns the value of its last
+    /// statement, or the error message.
+    fn eval(src: &str) -> Result<ValueView, String> {
+        eval_with(Interpreter::new(), src)
+    }
+
+    fn eval_with(mut interpreter: Interpreter, src: &str) -> Result<ValueView, String> {
+        crate::compile(src.to_string(), false)
+            .and_then(|stmts| interpreter.interpret_value(stmts))
+            .map_err(|e| e.to_string())
+    }
+
+    /// The expected result of a program ending in an array of integers.
+    fn integers(values: &[i64]) -> Result<ValueView, String> {
+        Ok(ValueView::Array(
+            values.iter().map(|&v| ValueView::Integer(v)).collect(),
+        ))
+    }
+
+    #[test]
+    fn and_or_skip_the_right_operand_once_decided() {
+        assert_eq!(
+            eval(r#"false and error("evaluated")"#),
+            Ok(ValueView::Boolean(false))
+        );
+        assert_eq!(
+            eval(r#"true or error("evaluated")"#),
+            Ok(ValueView::Boolean(true))
+        );
+        assert_eq!(eval("true and false"), Ok(ValueView::Boolean(false)));
+        assert_eq!(eval("false or true"), Ok(ValueView::Boolean(true)));
+        assert_eq!(
+            eval(r#"true and error("evaluated")"#),
+            Err("evaluated".to_string())
+        );
+    }
+
+    #[test]
+    fn and_or_reject_non_booleans() {
+        let error = Err("Runtime Error: 'and' operands must be booleans.".to_string());
+        assert_eq!(eval("1 and true"), error);
+        assert_eq!(eval("true and 1"), error);
+        assert_eq!(
+            eval("false or nil"),
+            Err("Runtime Error: 'or' operands must be booleans.".to_string())
+        );
+    }
+
+    #[test]
+    fn truthy_and_or_yield_the_deciding_operand() {
+        let truthy = || Interpreter::new().with_truthy_conditions(true);
+        assert_eq!(
+            eval_with(truthy(), r#"nil or "default""#),
+            Ok(ValueView::String("default".to_string()))
+        );
+        assert_eq!(
+            eval_with(truthy(), r#"0 and error("evaluated")"#),
+            Ok(ValueView::Integer(0))
+        );
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(eval("2 + 3 * 4"), Ok(ValueView::Integer(14)));
+        assert_eq!(eval("(2 + 3) * 4"), Ok(ValueView::Integer(20)));
+        assert_eq!(eval("7 / 2"), Ok(ValueView::Integer(3)));
+        assert_eq!(eval("5 / 2"), Ok(ValueView::Integer(2)));
+        assert_eq!(eval("5.0 / 2"), Ok(ValueView::Float(2.5)));
+        assert_eq!(eval("5 / 2.0"), Ok(ValueView::Float(2.5)));
+        assert_eq!(eval("7 % 3"), Ok(ValueView::Integer(1)));
+        assert_eq!(eval("1 + 0.5"), Ok(ValueView::Float(1.5)));
+        assert_eq!(
+            eval("1 / 0"),
+            Err("Runtime Error: Division by zero.".to_string())
+        );
+    }
+
+    #[test]
+    fn exit_unwinds_to_the_host() {
+        let mut interpreter = Interpreter::new();
+        let mut run = |src: &str| {
+            crate::compile(src.to_string(), false)
+                .and_then(|stmts| interpreter.interpret_value(stmts))
+        };
+        assert_eq!(
+            run("let mod reached = false\nexit(3)\nreached = true"),
+            Err(BloodError::Exit(3))
+        );
+        assert_eq!(run("reached"), Ok(ValueView::Boolean(false)));
+        // Leaving from inside functions is not reported as an error in them.
+        assert_eq!(
+            run("fn inner() do\n    exit()\nend\nfn outer() do\n    inner()\nend\nouter()"),
+            Err(BloodError::Exit(0))
+        );
+        assert_eq!(run("1 + 1"), Ok(ValueView::Integer(2)));
+    }
+
+    #[test]
+    fn common_runtime_errors_have_their_own_variants() {
+        let error = |src: &str| {
+            crate::compile(src.to_string(), false)
+                .and_then(|stmts| Interpreter::new().interpret_value(stmts))
+                .unwrap_err()
+        };
+        assert_eq!(
+            error("[1, 2][5]"),
+            BloodError::IndexOutOfBounds { index: 5, len: 2 }
+        );
+        assert_eq!(
+            error("let mod xs = [1]\nremove(xs, 0 - 1)"),
+            BloodError::IndexOutOfBounds { index: -1, len: 1 }
+        );
+        assert_eq!(
+            error("let x = 1\nx()"),
+            BloodError::NotAFunction("x".to_string())
+        );
+        assert_eq!(
+            error("let xs = [1]\nxs[0](2)"),
+            BloodError::NotAFunction("xs[0]".to_string())
+        );
+        assert_eq!(
+            error("9223372036854775807 + 1"),
+            BloodError::IntegerOverflow
+        );
+        assert_eq!(error("pow(2, 64)"), BloodError::IntegerOverflow);
+    }
+
+    #[test]
+    fn integer_overflow_is_an_error() {
+        let overflow = Err("Runtime Error: integer overflow".to_string());
+        assert_eq!(eval("let mod i = 9223372036854775807\ni++\ni"), overflow);
+        assert_eq!(
+            eval("let mod i = 0 - 9223372036854775807\ni--\ni--"),
+            overflow
+        );
+        assert_eq!(eval("4611686018427387904 * 2"), overflow);
+        assert_eq!(
+            eval("let min = 0 - 9223372036854775807 - 1\nmin / (0 - 1)"),
+            overflow
+        );
+        assert_eq!(
+            eval("let min = 0 - 9223372036854775807 - 1\nmin % (0 - 1)"),
+            overflow
+        );
+    }
+
+    #[test]
+    fn comparison_chains() {
+        assert_eq!(eval("1 < 2 < 3"), Ok(ValueView::Boolean(true)));
+        assert_eq!(eval("1 < 3 < 2"), Ok(ValueView::Boolean(false)));
+        assert_eq!(eval("1 <= 1 < 2 <= 2"), Ok(ValueView::Boolean(true)));
+        assert_eq!(eval("1 <= 1 < 2 <= 1"), Ok(ValueView::Boolean(false)));
+    }
+
+    #[test]
+    fn comparison_chains_evaluate_each_operand_once() {
+        let src = r#"
+            let mod calls = 0
+            fn middle() do
+                calls = calls + 1
+                if calls > 1 then
+                    error("middle evaluated twice")
+                end
+                return 2
+            end
+            [1 < middle() < 3, calls]
+        "#;
+        assert_eq!(
+            eval(src),
+            Ok(ValueView::Array(vec![
+                ValueView::Boolean(true),
+                ValueView::Integer(1)
+            ]))
+        );
+    }
+
+    #[test]
+    fn comparison_chains_stop_at_the_first_false_link() {
+        let src = r#"2 < 1 < error("evaluated")"#;
+        assert_eq!(eval(src), Ok(ValueView::Boolean(false)));
+    }
+
+    #[test]
+    fn inner_declarations_shadow_without_touching_the_outer_variable() {
+        let src = "
+            let x = 1
+            if true then
+                let x = 2
+            end
+            x
+        ";
+        assert_eq!(eval(src), Ok(ValueView::Integer(1)));
+    }
+
+    #[test]
+    fn assignment_reaches_the_enclosing_scope() {
+        let src = "
+            let mod total = 0
+            if true then
+                total = total + 5
+            end
+            total
+        ";
+        assert_eq!(eval(src), Ok(ValueView::Integer(5)));
+    }
+
+    #[test]
+    fn block_variables_end_with_their_block() {
+        let src = "
+            if true then
+                let inner = 1
+            end
+            inner
+        ";
+        assert_eq!(
+            eval(src),
+            Err("Runtime Error: Variable 'inner' not defined.".to_string())
+        );
+    }
+
+    #[test]
+    fn for_variables_stay_inside_the_loop() {
+        let src = "
+            for let mod i = 0; i < 3; i++ do
+            end
+            i
+        ";
+        assert_eq!(
+            eval(src),
+            Err("Runtime Error: Variable 'i' not defined.".to_string())
+        );
+
+        let src = "
+            let i = 10
+            let mod sum = 0
+            for let mod i = 0; i < 3; i++ do
+                sum = sum + i
+            end
+            [i, sum]
+        ";
+        assert_eq!(
+            eval(src),
+            Ok(ValueView::Array(vec![
+                ValueView::Integer(10),
+                ValueView::Integer(3)
+            ]))
+        );
+    }
+
+    #[test]
+    fn loops_and_break() {
+        let src = "
+            let mod n = 0
+            while true do
+                n = n + 1
+                if n == 4 then
+                    break
+                end
+            end
+            n
+        ";
+        assert_eq!(eval(src), Ok(ValueView::Integer(4)));
+
+        let src = "
+            let mod n = 0
+            repeat 3 times do
+                n = n + 2
+            end
+            repeat do
+                n = n - 1
+            until n == 2
+            n
+        ";
+        assert_eq!(eval(src), Ok(ValueView::Integer(2)));
+    }
+
+    #[test]
+    fn continue_in_while_rechecks_the_condition() {
+        let src = "
+            let mod n = 0
+            let mod seen = []
+            while n < 6 do
+                n = n + 1
+                if n % 2 == 0 then
+                    continue
+                end
+                seen = seen + [n]
+            end
+            seen + [n]
+        ";
+        assert_eq!(eval(src), integers(&[1, 3, 5, 6]));
+    }
+
+    #[test]
+    fn continue_in_for_still_runs_the_step() {
+        let src = "
+            let mod seen = []
+            for let mod i = 0; i < 6; i++ do
+                if i % 3 == 0 then
+                    continue
+                end
+                seen = seen + [i]
+            end
+            seen
+        ";
+        assert_eq!(eval(src), integers(&[1, 2, 4, 5]));
+    }
+
+    #[test]
+    fn continue_in_loop_starts_the_next_iteration() {
+        let src = "
+            let mod k = 0
+            let mod seen = []
+            loop do
+                k = k + 1
+                if k > 5 then
+                    break
+                end
+                if k == 2 then
+                    continue
+                end
+                seen = seen + [k]
+            end
+            seen
+        ";
+        assert_eq!(eval(src), integers(&[1, 3, 4, 5]));
+    }
+
+    #[test]
+    fn continue_in_repeat_counts_the_iteration() {
+        let src = "
+            let mod runs = 0
+            repeat 4 times do
+                runs = runs + 1
+                continue
+            end
+            let mod n = 0
+            repeat do
+                n = n + 1
+                continue
+            until n == 3
+            [runs, n]
+        ";
+        assert_eq!(eval(src), integers(&[4, 3]));
+    }
+
+    #[test]
+    fn continue_only_affects_the_innermost_loop() {
+        let src = "
+            let mod pairs = 0
+            for let mod i = 0; i < 3; i++ do
+                for let mod j = 0; j < 3; j++ do
+                    if j == i then
+                        continue
+                    end
+                    pairs = pairs + 1
+                end
+            end
+            pairs
+        ";
+        assert_eq!(eval(src), Ok(ValueView::Integer(6)));
+    }
+
+    #[test]
+    fn functions_return_and_recurse() {
+        let src = "
+            fn fib(n) do
+                if n < 2 then
+                    return n
+                end
+                return fib(n - 1) + fib(n - 2)
+            end
+            fib(10)
+        ";
+        assert_eq!(eval(src), Ok(ValueView::Integer(55)));
+    }
+
+    #[test]
+    fn bare_return_before_a_statement_keyword() {
+        let src = "
+            fn f() do
+                return
+                for let mod i = 0; i < 3; i++ do
+                    print(i)
+                end
+            end
+            f()
+        ";
+        assert_eq!(eval(src), Ok(ValueView::Nil));
+    }
+
+    const DISPATCH: &str = r#"
+        fn add(a, b) do
+            return a + b
+        end
+        fn sub(a, b) do
+            return a - b
+        end
+        let handlers = [add, sub]
+        let dispatch = {"add": add, "sub": sub, "limit": 10}
+    "#;
+
+    #[test]
+    fn functions_stored_in_arrays_can_be_called() {
+        let src = format!("{}[handlers[0](1, 2), handlers[1](5, 3)]", DISPATCH);
+        assert_eq!(eval(&src), integers(&[3, 2]));
+    }
+
+    #[test]
+    fn functions_stored_in_maps_can_be_called() {
+        let src = format!(r#"{}dispatch["add"](1, 2)"#, DISPATCH);
+        assert_eq!(eval(&src), Ok(ValueView::Integer(3)));
+    }
+
+    #[test]
+    fn calling_a_stored_non_function_fails() {
+        let src = format!(r#"{}dispatch["limit"](1)"#, DISPATCH);
+        assert_eq!(
+            eval(&src),
+            Err(r#"Runtime Error: 'dispatch["limit"]' is not a function."#.to_string())
+        );
+        let src = format!(r#"{}dispatch["missing"]()"#, DISPATCH);
+        assert_eq!(
+            eval(&src),
+            Err(r#"Runtime Error: 'dispatch["missing"]' is not a function."#.to_string())
+        );
+    }
+
+    #[test]
+    fn call_arguments_must_start_on_the_same_line() {
+        let src = format!("{}add\n(7)", DISPATCH);
+        assert_eq!(eval(&src), Ok(ValueView::Integer(7)));
+        let src = format!("{}handlers[0]\n(7)", DISPATCH);
+        assert_eq!(eval(&src), Ok(ValueView::Integer(7)));
+        let src = format!("{}add(1, 2)\n(7)", DISPATCH);
+        assert_eq!(eval(&src), Ok(ValueView::Integer(7)));
+        // Arguments may still continue onto later lines.
+        let src = format!("{}add(1,\n2)", DISPATCH);
+        assert_eq!(eval(&src), Ok(ValueView::Integer(3)));
+    }
+
+    #[test]
+    fn indexing() {
+        assert_eq!(eval("[[1, 2], [3]][0][1]"), Ok(ValueView::Integer(2)));
+        assert_eq!(eval(r#"{"a": 1}["b"]"#), Ok(ValueView::Nil));
+        // On a new line, `[` starts an array rather than indexing.
+        assert_eq!(eval("let xs = [1]\n[2]"), integers(&[2]));
+        assert_eq!(
+            eval("[1][1]"),
+            Err("Runtime Error: Index 1 is out of bounds for an array of length 1.".to_string())
+        );
+    }
+
+    #[test]
+    fn functions_cannot_take_a_builtins_name() {
+        let error = Err("Runtime Error: Cannot redefine built-in 'map'.".to_string());
+        let src = "
+            fn double(x) do
+                return x * 2
+            end
+        ";
+        assert_eq!(eval(&format!("{}fn map(x) do\nend", src)), error);
+        assert_eq!(eval(&format!("{}let map = double", src)), error);
+        assert_eq!(
+            eval(&format!("{}let mod map = 1\nmap = double", src)),
+            error
+        );
+        let apply = format!(
+            "{}fn apply(map, x) do\nreturn map(x)\nend\napply(double, 1)",
+            src
+        );
+        assert_eq!(
+            eval(&apply),
+            Err(
+                "Runtime Error: Cannot redefine built-in 'map'.\n  in fn apply\n  at top level"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn other_values_can_take_a_builtins_name() {
+        assert_eq!(
+            eval("let sum = 1\nsum([sum, 2])"),
+            Ok(ValueView::Integer(3))
+        );
+    }
+
+    #[test]
+    fn register_native_rejects_builtin_names() {
+        let mut interpreter = Interpreter::new();
+        let result = interpreter.register_native("max", Box::new(|_| Ok(ValueView::Nil)));
+        assert_eq!(
+            result,
+            Err(BloodError::Runtime(
+                "Runtime Error: Cannot redefine built-in 'max'.".to_string()
+            ))
+        );
+        assert!(
+            interpreter
+                .register_native("host_max", Box::new(|_| Ok(ValueView::Nil)))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn elseif_takes_the_first_matching_branch() {
+        let src = r#"
+            fn sign(n) do
+                if n < 0 then
+                    return "negative"
+                elseif n == 0 then
+                    return "zero"
+                else if n < 10 then
+                    return "small"
+                else
+                    return "large"
+                end
+            end
+            [sign(0 - 1), sign(0), sign(5), sign(50)]
+        "#;
+        let expected = ["negative", "zero", "small", "large"]
+            .iter()
+            .map(|s| ValueView::String(s.to_string()))
+            .collect();
+        assert_eq!(eval(src), Ok(ValueView::Array(expected)));
+    }
+
+    #[test]
+    fn conditions_must_be_booleans_unless_truthy() {
+        let src = r#"
+            if 0 then
+                "yes"
+            end
+        "#;
+        assert_eq!(
+            eval(src),
+            Err("Runtime error: condition must be boolean".to_string())
+        );
+
+        let src = r#"
+            let mod seen = "none"
+            if "" then
+                seen = "empty string"
+            elseif [0] then
+                seen = "array"
+            end
+            seen
+        "#;
+        assert_eq!(
+            eval_with(Interpreter::new().with_truthy_conditions(true), src),
+            Ok(ValueView::String("array".to_string()))
+        );
+    }
+
+    #[test]
+    fn truthy_conditions_treat_empty_and_zero_values_as_false() {
+        let truthy = || Interpreter::new().with_truthy_conditions(true);
+        let check = "
+            fn passes(value) do
+                if value then
+                    return true
+                end
+                return false
+            end
+        ";
+        for falsy in ["false", "0", "0.0", "nil", r#""""#, "[]", "{}"] {
+            assert_eq!(
+                eval_with(truthy(), &format!("{}passes({})", check, falsy)),
+                Ok(ValueView::Boolean(false)),
+                "{} should be falsy",
+                falsy
+            );
+        }
+        for value in ["true", "1", "0.5", r#"" ""#, "[0]", r#"{"a": nil}"#] {
+            assert_eq!(
+                eval_with(truthy(), &format!("{}passes({})", check, value)),
+                Ok(ValueView::Boolean(true)),
+                "{} should be truthy",
+                value
+            );
         }
     }
+
+    #[test]
+    fn equality() {
+        assert_eq!(eval("1 == 1.0"), Ok(ValueView::Boolean(false)));
+        assert_eq!(eval(r#"1 == "1""#), Ok(ValueView::Boolean(false)));
+        assert_eq!(eval("[1, [2]] == [1, [2]]"), Ok(ValueView::Boolean(true)));
+        assert_eq!(
+            eval(r#"{"a": 1, "b": 2} == {"b": 2, "a": 1}"#),
+            Ok(ValueView::Boolean(true))
+        );
+        assert_eq!(eval("nil != false"), Ok(ValueView::Boolean(true)));
+        let functions = "
+            fn f(x) do
+                return x
+            end
+            fn g(x) do
+                return x
+            end
+            let alias = f
+        ";
+        for (expr, expected) in [
+            ("f == f", true),
+            ("alias == f", true),
+            ("f == g", false),
+            ("f == 1", false),
+            (r#"f == "f""#, false),
+            ("f != nil", true),
+        ] {
+            assert_eq!(
+                eval(&format!("{}{}", functions, expr)),
+                Ok(ValueView::Boolean(expected)),
+                "{}",
+                expr
+            );
+        }
+    }
+
+    #[test]
+    fn native_and_memoized_functions_compare_by_identity() {
+        let interpreter = || {
+            let mut interpreter = Interpreter::new();
+            for name in ["one", "other_one"] {
+                interpreter
+                    .register_native(name, Box::new(|_| Ok(ValueView::Integer(1))))
+                    .unwrap();
+            }
+            interpreter
+        };
+        let src = "
+            fn f(x) do
+                return x
+            end
+            let fast = memoize(f)
+            let alias = fast
+        ";
+        for (expr, expected) in [
+            ("one == one", true),
+            ("one == other_one", false),
+            ("fast == alias", true),
+            ("fast == memoize(f)", false),
+            ("fast == f", false),
+            ("one == fast", false),
+        ] {
+            assert_eq!(
+                eval_with(interpreter(), &format!("{}{}", src, expr)),
+                Ok(ValueView::Boolean(expected)),
+                "{}",
+                expr
+            );
+        }
+    }
+
+    #[test]
+    fn block_comments_nest() {
+        assert_eq!(
+            eval("/* outer /* inner */ still comment */ 1"),
+            Ok(ValueView::Integer(1))
+        );
+        assert_eq!(
+            eval("1\n/* never /* closed */"),
+            Err("Syntax Error on line 2: Unterminated block comment".to_string())
+        );
+    }
 }
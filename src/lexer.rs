@@ -1,3 +1,6 @@
+use crate::error::BloodError;
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Let,
@@ -11,10 +14,15 @@ pub enum Token {
     While,
     Do,
     Loop,
+    Repeat,
+    Times,
+    Until,
+    For,
     Break,
     Continue,
     Fn,
     Return,
+    Import,
     Nil,
     True,
     False,
@@ -23,8 +31,13 @@ pub enum Token {
     Not,
     Identifier(String),
     Number(i64),
+    Float(f64),
+    String(String),
+    Char(char),
     Plus,
     Minus,
+    PlusPlus,   // ++
+    MinusMinus, // --
     Star,
     Slash,
     Percent,      // %
@@ -37,13 +50,59 @@ pub enum Token {
     GreaterEqual, // >=
     LParen,
     RParen,
-    Comma, // ,
+    LBracket,         // [
+    RBracket,         // ]
+    LBrace,           // {
+    RBrace,           // }
+    Comma,            // ,
+    Semicolon,        // ;
+    Colon,            // :
+    QuestionQuestion, // ??
+    Ellipsis,         // ...
+    Newline,          // only emitted with newline terminators enabled
     EOF,
 }
 
+impl Token {
+    /// Whether a statement can end with this token. A line break only
+    /// terminates a statement when it follows one of these.
+    fn can_end_statement(&self) -> bool {
+        matches!(
+            self,
+            Token::Identifier(_)
+                | Token::Number(_)
+                | Token::Float(_)
+                | Token::String(_)
+                | Token::Char(_)
+                | Token::Nil
+                | Token::True
+                | Token::False
+                | Token::RParen
+                | Token::RBracket
+                | Token::RBrace
+                | Token::End
+                | Token::Break
+                | Token::Continue
+                | Token::Return
+                | Token::PlusPlus
+                | Token::MinusMinus
+        )
+    }
+}
+
 pub struct Lexer {
     input: Vec<char>,
     position: usize,
+    // Line of `position`, and of the start of the most recent token.
+    line: usize,
+    token_line: usize,
+    newline_terminators: bool,
+    // Set when the last token could end a statement, so the next line break
+    // is significant.
+    at_statement_end: bool,
+    // Number of `(`, `[` and `{` not yet closed. Line breaks inside them never
+    // end a statement.
+    bracket_depth: usize,
 }
 
 impl Lexer {
@@ -51,19 +110,69 @@ impl Lexer {
         Self {
             input: input.chars().collect(),
             position: 0,
+            line: 1,
+            token_line: 1,
+            newline_terminators: false,
+            at_statement_end: false,
+            bracket_depth: 0,
+        }
+    }
+
+    /// When enabled, a line break after a token that can end a statement is
+    /// emitted as `Token::Newline`. Line breaks anywhere else (after an
+    /// operator, `do`, `then`, a comma, or another line break) are skipped,
+    /// so an expression can still continue onto the next line. Inside
+    /// parentheses or brackets, no line break is significant.
+    pub fn with_newline_terminators(mut self, enabled: bool) -> Self {
+        self.newline_terminators = enabled;
+        self
+    }
+
+    pub fn newline_terminators(&self) -> bool {
+        self.newline_terminators
+    }
+
+    /// The line the most recently returned token starts on.
+    pub fn token_line(&self) -> usize {
+        self.token_line
+    }
+
+    pub fn next_token(&mut self) -> Result<Token, BloodError> {
+        let token = self.scan_token()?;
+        match token {
+            Token::LParen | Token::LBracket | Token::LBrace => self.bracket_depth += 1,
+            Token::RParen | Token::RBracket | Token::RBrace => {
+                self.bracket_depth = self.bracket_depth.saturating_sub(1)
+            }
+            _ => {}
+        }
+        self.at_statement_end = self.bracket_depth == 0 && token.can_end_statement();
+        Ok(token)
+    }
+
+    fn error(&self, message: String) -> BloodError {
+        BloodError::Parse {
+            line: self.line,
+            message,
         }
     }
 
-    pub fn next_token(&mut self) -> Token {
+    fn scan_token(&mut self) -> Result<Token, BloodError> {
         self.skip_whitespace();
+        self.token_line = self.line;
 
         if self.position >= self.input.len() {
-            return Token::EOF;
+            return Ok(Token::EOF);
         }
 
         let ch = self.input[self.position];
 
-        if ch.is_digit(10) {
+        if ch == '\n' {
+            self.advance();
+            return Ok(Token::Newline);
+        }
+
+        if ch.is_ascii_digit() {
             return self.read_number();
         }
 
@@ -71,14 +180,30 @@ impl Lexer {
             return self.read_identifier();
         }
 
-        match ch {
+        if ch == '"' {
+            return self.read_string();
+        }
+
+        if ch == '\'' {
+            return self.read_char();
+        }
+
+        let token = match ch {
             '+' => {
                 self.advance();
-                Token::Plus
+                if self.match_char('+') {
+                    Token::PlusPlus
+                } else {
+                    Token::Plus
+                }
             }
             '-' => {
                 self.advance();
-                Token::Minus
+                if self.match_char('-') {
+                    Token::MinusMinus
+                } else {
+                    Token::Minus
+                }
             }
             '*' => {
                 self.advance();
@@ -91,23 +216,10 @@ impl Lexer {
                     while self.position < self.input.len() && self.input[self.position] != '\n' {
                         self.advance();
                     }
-                    self.next_token()
+                    return self.scan_token();
                 } else if self.match_char('*') {
-                    // Multi-line comment
-                    loop {
-                        if self.position >= self.input.len() {
-                            break;
-                        }
-                        if self.input[self.position] == '*' {
-                            self.advance();
-                            if self.match_char('/') {
-                                break;
-                            }
-                        } else {
-                            self.advance();
-                        }
-                    }
-                    self.next_token()
+                    self.skip_block_comment()?;
+                    return self.scan_token();
                 } else {
                     Token::Slash
                 }
@@ -124,10 +236,50 @@ impl Lexer {
                 self.advance();
                 Token::RParen
             }
+            '[' => {
+                self.advance();
+                Token::LBracket
+            }
+            ']' => {
+                self.advance();
+                Token::RBracket
+            }
+            '{' => {
+                self.advance();
+                Token::LBrace
+            }
+            '}' => {
+                self.advance();
+                Token::RBrace
+            }
+            '.' => {
+                self.advance();
+                if self.match_char('.') && self.match_char('.') {
+                    Token::Ellipsis
+                } else {
+                    return Err(self.error("Unexpected character: .".to_string()));
+                }
+            }
             ',' => {
                 self.advance();
                 Token::Comma
             }
+            ';' => {
+                self.advance();
+                Token::Semicolon
+            }
+            ':' => {
+                self.advance();
+                Token::Colon
+            }
+            '?' => {
+                self.advance();
+                if self.match_char('?') {
+                    Token::QuestionQuestion
+                } else {
+                    return Err(self.error("Unexpected character: ?".to_string()));
+                }
+            }
             '=' => {
                 self.advance();
                 if self.match_char('=') {
@@ -141,7 +293,7 @@ impl Lexer {
                 if self.match_char('=') {
                     Token::BangEqual
                 } else {
-                    panic!("Unexpected character: !");
+                    return Err(self.error("Unexpected character: !".to_string()));
                 }
             }
             '<' => {
@@ -160,11 +312,15 @@ impl Lexer {
                     Token::Greater
                 }
             }
-            _ => panic!("Unexpected character: {}", ch),
-        }
+            _ => return Err(self.error(format!("Unexpected character: {}", ch))),
+        };
+        Ok(token)
     }
 
     fn advance(&mut self) {
+        if self.input.get(self.position) == Some(&'\n') {
+            self.line += 1;
+        }
         self.position += 1;
     }
 
@@ -175,26 +331,253 @@ impl Lexer {
         if self.input[self.position] != expected {
             return false;
         }
-        self.position += 1;
+        self.advance();
         true
     }
 
     fn skip_whitespace(&mut self) {
+        let keep_newline = self.newline_terminators && self.at_statement_end;
         while self.position < self.input.len() && self.input[self.position].is_whitespace() {
+            if keep_newline && self.input[self.position] == '\n' {
+                break;
+            }
             self.advance();
         }
     }
 
-    fn read_number(&mut self) -> Token {
-        let start = self.position;
-        while self.position < self.input.len() && self.input[self.position].is_digit(10) {
+    /// Skips a `/* ... */` comment whose opening `/*` was just consumed.
+    /// Comments nest, so each inner `/*` needs its own `*/`.
+    fn skip_block_comment(&mut self) -> Result<(), BloodError> {
+        let start_line = self.line;
+        let mut depth = 1;
+        while depth > 0 {
+            if self.position >= self.input.len() {
+                return Err(BloodError::Parse {
+                    line: start_line,
+                    message: "Unterminated block comment".to_string(),
+                });
+            }
+            if self.match_char('/') {
+                if self.match_char('*') {
+                    depth += 1;
+                }
+            } else if self.match_char('*') {
+                if self.match_char('/') {
+                    depth -= 1;
+                }
+            } else {
+                self.advance();
+            }
+        }
+        Ok(())
+    }
+
+    fn read_number(&mut self) -> Result<Token, BloodError> {
+        if self.input[self.position] == '0' && self.position + 1 < self.input.len() {
+            match self.input[self.position + 1] {
+                'x' | 'X' => return self.read_radix_number(16, "hexadecimal"),
+                'b' | 'B' => return self.read_radix_number(2, "binary"),
+                'o' | 'O' => return self.read_radix_number(8, "octal"),
+                _ => {}
+            }
+        }
+
+        let integer = self.read_digits(10)?;
+        // `017` could be meant as octal, so it is rejected rather than read as 17.
+        if integer.len() > 1 && integer.starts_with('0') {
+            return Err(self.error(format!(
+                "Leading zeros are not allowed in number literal {}; use the 0o prefix for octal",
+                integer
+            )));
+        }
+
+        // A '.' only starts a fraction when a digit follows it.
+        let has_fraction = self.position + 1 < self.input.len()
+            && self.input[self.position] == '.'
+            && self.input[self.position + 1].is_ascii_digit();
+        let mut text = integer.clone();
+        if has_fraction {
+            self.advance();
+            let fraction = self.read_digits(10)?;
+            text = format!("{}.{}", text, fraction);
+        }
+        // An exponent makes the literal a float even without a fraction.
+        let has_exponent = matches!(self.input.get(self.position), Some('e' | 'E'));
+        if has_exponent {
             self.advance();
+            let sign = match self.input.get(self.position) {
+                Some(&sign @ ('+' | '-')) => {
+                    self.advance();
+                    sign.to_string()
+                }
+                _ => String::new(),
+            };
+            let exponent = self.read_digits(10)?;
+            if exponent.is_empty() {
+                return Err(self.error(format!(
+                    "Missing digits in the exponent of number literal {}e{}",
+                    text, sign
+                )));
+            }
+            text = format!("{}e{}{}", text, sign, exponent);
+        }
+        if has_fraction || has_exponent {
+            let value: f64 = text.parse().unwrap();
+            if value.is_infinite() {
+                return Err(self.error(format!("Out of range float literal: {}", text)));
+            }
+            return Ok(Token::Float(value));
+        }
+
+        match integer.parse() {
+            Ok(value) => Ok(Token::Number(value)),
+            Err(_) => Err(self.error(format!("Out of range integer literal: {}", integer))),
+        }
+    }
+
+    fn read_radix_number(&mut self, radix: u32, kind: &str) -> Result<Token, BloodError> {
+        self.advance(); // 0
+        self.advance(); // x / b
+        let digits = self.read_digits(radix)?;
+        if self.position < self.input.len() && self.input[self.position].is_alphanumeric() {
+            return Err(self.error(format!(
+                "Invalid digit '{}' in {} literal",
+                self.input[self.position], kind
+            )));
+        }
+        if digits.is_empty() {
+            return Err(self.error(format!("Missing digits in {} literal", kind)));
+        }
+        match i64::from_str_radix(&digits, radix) {
+            Ok(value) => Ok(Token::Number(value)),
+            Err(_) => Err(self.error(format!("Out of range {} literal: {}", kind, digits))),
+        }
+    }
+
+    /// Reads a run of digits in `radix`, dropping single `_` separators. An
+    /// underscore must sit between two digits.
+    fn read_digits(&mut self, radix: u32) -> Result<String, BloodError> {
+        let mut digits = String::new();
+        while self.position < self.input.len() {
+            let ch = self.input[self.position];
+            if ch == '_' {
+                let next_is_digit = self.position + 1 < self.input.len()
+                    && self.input[self.position + 1].is_digit(radix);
+                if digits.is_empty() || !next_is_digit {
+                    return Err(self.error("Misplaced '_' separator in number literal".to_string()));
+                }
+            } else if ch.is_digit(radix) {
+                digits.push(ch);
+            } else {
+                break;
+            }
+            self.advance();
+        }
+        Ok(digits)
+    }
+
+    fn read_string(&mut self) -> Result<Token, BloodError> {
+        self.advance(); // opening quote
+        let mut text = String::new();
+        while self.position < self.input.len() && self.input[self.position] != '"' {
+            if self.input[self.position] == '\\' {
+                self.advance();
+                text.push(self.read_escape()?);
+            } else {
+                text.push(self.input[self.position]);
+                self.advance();
+            }
+        }
+        if self.position >= self.input.len() {
+            return Err(self.error("Unterminated string literal".to_string()));
+        }
+        self.advance(); // closing quote
+        Ok(Token::String(text))
+    }
+
+    fn read_char(&mut self) -> Result<Token, BloodError> {
+        self.advance(); // opening quote
+        let ch = match self.input.get(self.position) {
+            None => return Err(self.error("Unterminated character literal".to_string())),
+            Some('\'') => return Err(self.error("Empty character literal".to_string())),
+            Some('\\') => {
+                self.advance();
+                self.read_escape()?
+            }
+            Some(&ch) => {
+                self.advance();
+                ch
+            }
+        };
+        if !self.match_char('\'') {
+            return Err(
+                self.error("Character literal must contain exactly one character".to_string())
+            );
         }
-        let number_str: String = self.input[start..self.position].iter().collect();
-        Token::Number(number_str.parse().unwrap())
+        Ok(Token::Char(ch))
+    }
+
+    /// Decodes the escape sequence following a backslash.
+    fn read_escape(&mut self) -> Result<char, BloodError> {
+        let ch = match self.input.get(self.position) {
+            Some(&ch) => ch,
+            None => return Err(self.error("Unterminated escape sequence".to_string())),
+        };
+        self.advance();
+        let decoded = match ch {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '\\' => '\\',
+            '\'' => '\'',
+            '"' => '"',
+            'x' => {
+                let digits = self.read_hex_escape_digits(2);
+                if digits.len() != 2 {
+                    return Err(self.error("\\x escape needs exactly two hex digits".to_string()));
+                }
+                self.code_point(&digits)?
+            }
+            'u' => {
+                if !self.match_char('{') {
+                    return Err(self.error("\\u escape must be written as \\u{...}".to_string()));
+                }
+                let digits = self.read_hex_escape_digits(6);
+                if digits.is_empty() || !self.match_char('}') {
+                    return Err(self.error("\\u{...} escape needs 1 to 6 hex digits".to_string()));
+                }
+                self.code_point(&digits)?
+            }
+            _ => return Err(self.error(format!("Unknown escape sequence: \\{}", ch))),
+        };
+        Ok(decoded)
     }
 
-    fn read_identifier(&mut self) -> Token {
+    /// Reads up to `max` hex digits of a `\x` or `\u{...}` escape.
+    fn read_hex_escape_digits(&mut self, max: usize) -> String {
+        let mut digits = String::new();
+        while digits.len() < max {
+            match self.input.get(self.position) {
+                Some(ch) if ch.is_ascii_hexdigit() => {
+                    digits.push(*ch);
+                    self.advance();
+                }
+                _ => break,
+            }
+        }
+        digits
+    }
+
+    /// Decodes hex `digits` into a character, rejecting surrogates and
+    /// values past `10FFFF`.
+    fn code_point(&self, digits: &str) -> Result<char, BloodError> {
+        let value = u32::from_str_radix(digits, 16).unwrap();
+        char::from_u32(value)
+            .ok_or_else(|| self.error(format!("Invalid Unicode code point: {:X}", value)))
+    }
+
+    fn read_identifier(&mut self) -> Result<Token, BloodError> {
         let start = self.position;
         while self.position < self.input.len()
             && (self.input[self.position].is_alphanumeric() || self.input[self.position] == '_')
@@ -203,7 +586,7 @@ impl Lexer {
         }
         let text: String = self.input[start..self.position].iter().collect();
 
-        match text.as_str() {
+        Ok(match text.as_str() {
             "let" => Token::Let,
             "mod" => Token::Mod,
             "print" => Token::Print,
@@ -215,10 +598,15 @@ impl Lexer {
             "while" => Token::While,
             "do" => Token::Do,
             "loop" => Token::Loop,
+            "repeat" => Token::Repeat,
+            "times" => Token::Times,
+            "until" => Token::Until,
+            "for" => Token::For,
             "break" => Token::Break,
             "continue" => Token::Continue,
             "fn" => Token::Fn,
             "return" => Token::Return,
+            "import" => Token::Import,
             "nil" => Token::Nil,
             "true" => Token::True,
             "false" => Token::False,
@@ -226,6 +614,72 @@ impl Lexer {
             "or" => Token::Or,
             "not" => Token::Not,
             _ => Token::Identifier(text),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(src: &str) -> Result<Token, String> {
+        Lexer::new(src.to_string())
+            .next_token()
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn underscores_separate_digits() {
+        assert_eq!(number("1_000_000"), Ok(Token::Number(1_000_000)));
+        assert_eq!(number("0xFF_FF"), Ok(Token::Number(0xFFFF)));
+        assert_eq!(number("0b1010_1010"), Ok(Token::Number(0b1010_1010)));
+        assert_eq!(number("1_000.000_5"), Ok(Token::Float(1000.0005)));
+    }
+
+    #[test]
+    fn underscores_must_sit_between_digits() {
+        for src in ["5_", "1__0", "0x_FF", "1_.5"] {
+            let err = number(src).unwrap_err();
+            assert!(err.contains("Misplaced '_' separator"), "{}: {}", src, err);
+        }
+    }
+
+    /// Lexes `src` with newline terminators on, up to and excluding EOF.
+    fn line_tokens(src: &str) -> Vec<Token> {
+        let mut lexer = Lexer::new(src.to_string()).with_newline_terminators(true);
+        let mut tokens = Vec::new();
+        loop {
+            match lexer.next_token().unwrap() {
+                Token::EOF => return tokens,
+                token => tokens.push(token),
+            }
+        }
+    }
+
+    #[test]
+    fn brackets_after_comments_are_counted_once() {
+        for src in ["x = /* c */ (1)\ny", "x = // c\n(1)\ny"] {
+            let tokens = line_tokens(src);
+            assert_eq!(tokens.last(), Some(&Token::Identifier("y".to_string())));
+            assert_eq!(tokens[tokens.len() - 2], Token::Newline, "{}", src);
         }
     }
+
+    #[test]
+    fn line_breaks_inside_brackets_are_skipped() {
+        assert_eq!(
+            line_tokens("x = (1 +\n2)\ny"),
+            vec![
+                Token::Identifier("x".to_string()),
+                Token::Equal,
+                Token::LParen,
+                Token::Number(1),
+                Token::Plus,
+                Token::Number(2),
+                Token::RParen,
+                Token::Newline,
+                Token::Identifier("y".to_string()),
+            ]
+        );
+    }
 }
@@ -0,0 +1,51 @@
+//! The Blood interpreter as a library, for embedding in Rust programs.
+//!
+//! `run` and `eval` cover the common cases. For anything more (flags, reading
+//! globals back out), build an `Interpreter` and feed it the output of `compile`.
+
+pub mod ast;
+mod error;
+mod format;
+pub mod interpreter;
+mod lexer;
+mod lint;
+mod optimizer;
+mod parser;
+mod resolver;
+
+pub use error::BloodError;
+pub use interpreter::{Interpreter, NativeFn, ValueView};
+use lexer::Lexer;
+pub use lint::unused_variables;
+pub use optimizer::optimize;
+use parser::Parser;
+
+/// Lexes, parses and prepares a program for the interpreter. With
+/// `newline_terminators`, line breaks end statements.
+///
+/// Constant expressions are left as written; pass the result through
+/// `optimize` to fold them first, as `run`, `eval` and `blood` do.
+pub fn compile(code: String, newline_terminators: bool) -> Result<Vec<ast::Stmt>, BloodError> {
+    let lexer = Lexer::new(code).with_newline_terminators(newline_terminators);
+    let mut parser = Parser::new(lexer)?;
+    Ok(resolver::resolve(parser.parse_program()?))
+}
+
+/// Parses a program and prints it back in the canonical layout used by
+/// `blood fmt`. Comments are dropped.
+pub fn format_source(code: String, newline_terminators: bool) -> Result<String, BloodError> {
+    let lexer = Lexer::new(code).with_newline_terminators(newline_terminators);
+    let mut parser = Parser::new(lexer)?;
+    Ok(format::format_program(&parser.parse_program()?))
+}
+
+/// Runs a program with the default options.
+pub fn run(source: &str) -> Result<(), BloodError> {
+    Interpreter::new().interpret(optimize(compile(source.to_string(), false)?))
+}
+
+/// Runs a program with the default options and returns the value of its last
+/// statement if that is an expression (`nil` otherwise), e.g. `eval("1 + 2")`.
+pub fn eval(source: &str) -> Result<ValueView, BloodError> {
+    Interpreter::new().interpret_value(optimize(compile(source.to_string(), false)?))
+}
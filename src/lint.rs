@@ -0,0 +1,289 @@
+use crate::ast::{Expr, Stmt};
+use std::collections::HashSet;
+
+/// Finds `let` bindings that are never read and returns a warning for each.
+///
+/// Globals count as read if any code reads them, even from a function body
+/// written above the `let`. A variable's own assignment doesn't count as a
+/// read, so a counter that is only ever bumped with `x++` or `x = x + 1` is
+/// still reported. Function parameters and names starting with `_` are never
+/// reported.
+pub fn unused_variables(stmts: &[Stmt]) -> Vec<String> {
+    let mut lint = Lint {
+        globals: Vec::new(),
+        global_reads: HashSet::new(),
+        scopes: Vec::new(),
+        assigning: None,
+        warnings: Vec::new(),
+    };
+    lint.block(stmts);
+    for name in std::mem::take(&mut lint.globals) {
+        if !lint.global_reads.contains(&name) {
+            lint.warn(&name);
+        }
+    }
+    lint.warnings
+}
+
+struct Lint {
+    // Global `let` names in declaration order, and every name read that did
+    // not resolve to a local.
+    globals: Vec<String>,
+    global_reads: HashSet<String>,
+    // Local scopes of the code being walked: each entry is a name and
+    // whether it has been read. Empty at the top level.
+    scopes: Vec<Vec<(String, bool)>>,
+    // The variable whose new value is being walked, if any.
+    assigning: Option<String>,
+    warnings: Vec<String>,
+}
+
+impl Lint {
+    fn warn(&mut self, name: &str) {
+        if !name.starts_with('_') {
+            self.warnings.push(format!(
+                "Warning: Variable '{}' is declared but never read.",
+                name
+            ));
+        }
+    }
+
+    fn declare(&mut self, name: &str) {
+        match self.scopes.last_mut() {
+            Some(scope) => scope.push((name.to_string(), false)),
+            None => self.globals.push(name.to_string()),
+        }
+    }
+
+    /// Declares a name that is exempt from warnings, such as a parameter.
+    fn declare_used(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push((name.to_string(), true));
+        }
+    }
+
+    fn read(&mut self, name: &str) {
+        if self.assigning.as_deref() == Some(name) {
+            return;
+        }
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(entry) = scope.iter_mut().rev().find(|(n, _)| n == name) {
+                entry.1 = true;
+                return;
+            }
+        }
+        self.global_reads.insert(name.to_string());
+    }
+
+    fn scoped_block(&mut self, stmts: &[Stmt]) {
+        self.scopes.push(Vec::new());
+        self.block(stmts);
+        self.end_scope();
+    }
+
+    /// Pops the innermost scope, warning about its unread variables.
+    fn end_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap();
+        for (name, used) in scope {
+            if !used {
+                self.warn(&name);
+            }
+        }
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Let { name, value, .. } => {
+                self.expr(value);
+                self.declare(name);
+            }
+            Stmt::LetMulti { names, value, .. } => {
+                self.expr(value);
+                for name in names {
+                    self.declare(name);
+                }
+            }
+            Stmt::Assign { name, value } => {
+                self.assigning = Some(name.clone());
+                self.expr(value);
+                self.assigning = None;
+            }
+            Stmt::Print(expr) | Stmt::Return(expr) | Stmt::ExprStmt(expr) => self.expr(expr),
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(condition);
+                self.scoped_block(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.scoped_block(else_branch);
+                }
+            }
+            Stmt::While {
+                condition,
+                body,
+                else_branch,
+            } => {
+                self.expr(condition);
+                self.scoped_block(body);
+                if let Some(else_branch) = else_branch {
+                    self.scoped_block(else_branch);
+                }
+            }
+            Stmt::Loop { body } => self.scoped_block(body),
+            Stmt::Repeat { count, body } => {
+                self.expr(count);
+                self.scoped_block(body);
+            }
+            Stmt::DoWhile { body, condition } => {
+                self.scoped_block(body);
+                self.expr(condition);
+            }
+            Stmt::For {
+                init,
+                condition,
+                step,
+                body,
+            } => {
+                self.scopes.push(Vec::new());
+                self.block(init);
+                self.expr(condition);
+                self.scoped_block(body);
+                self.block(step);
+                self.end_scope();
+            }
+            Stmt::Fn { params, body, .. } => {
+                // A function body only sees its own locals and the globals.
+                let enclosing = std::mem::replace(&mut self.scopes, vec![Vec::new()]);
+                for param in params {
+                    if let Some(default) = &param.default {
+                        self.expr(default);
+                    }
+                    self.declare_used(&param.name);
+                }
+                self.block(body);
+                self.end_scope();
+                self.scopes = enclosing;
+            }
+            Stmt::Break | Stmt::Continue | Stmt::Import(_) => {}
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Variable(name) | Expr::ResolvedVariable { name, .. } => self.read(name),
+            Expr::Binary(left, _, right) => {
+                self.expr(left);
+                self.expr(right);
+            }
+            Expr::Unary(_, right) | Expr::Grouping(right) => self.expr(right),
+            Expr::Chain(first, rest) => {
+                self.expr(first);
+                for (_, operand) in rest {
+                    self.expr(operand);
+                }
+            }
+            Expr::Array(elements) => elements.iter().for_each(|e| self.expr(e)),
+            Expr::Map(entries) => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            Expr::Call(name, args) => {
+                self.read(name);
+                args.iter().for_each(|e| self.expr(e));
+            }
+            Expr::Index(collection, index) => {
+                self.expr(collection);
+                self.expr(index);
+            }
+            Expr::CallValue(callee, args) => {
+                self.expr(callee);
+                args.iter().for_each(|e| self.expr(e));
+            }
+            Expr::Number(_) | Expr::Float(_) | Expr::String(_) | Expr::Boolean(_) | Expr::Nil => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The names `unused_variables` reports for `src`.
+    fn unused(src: &str) -> Vec<String> {
+        let program = crate::compile(src.to_string(), false).unwrap();
+        unused_variables(&program)
+            .iter()
+            .map(|warning| warning.split('\'').nth(1).unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn reports_lets_that_are_never_read() {
+        assert_eq!(unused("let x = 1\nlet y = 2\nprint(y)"), ["x"]);
+        assert!(unused("let x = 1\nprint(x)").is_empty());
+    }
+
+    #[test]
+    fn reads_in_inner_scopes_count() {
+        assert!(unused("let x = 1\nif true then\n    print(x)\nend").is_empty());
+        assert!(unused("let x = 1\nfn f() do\n    return x\nend\nprint(f())").is_empty());
+    }
+
+    #[test]
+    fn shadowed_names_are_tracked_separately() {
+        let src = "
+            let x = 1
+            if true then
+                let x = 2
+            end
+            print(x)
+        ";
+        assert_eq!(unused(src), ["x"]);
+        let src = "
+            fn f() do
+                let x = 1
+                if true then
+                    let x = 2
+                    print(x)
+                end
+            end
+            f()
+        ";
+        assert_eq!(unused(src), ["x"]);
+    }
+
+    #[test]
+    fn for_setup_variables_are_checked() {
+        assert!(unused("for let mod i = 0; i < 3; i++ do\nend").is_empty());
+        assert_eq!(
+            unused("for let mod i = 0, let mod n = 0; i < 3; i++, n++ do\nend"),
+            ["n"]
+        );
+    }
+
+    #[test]
+    fn underscore_names_and_parameters_are_exempt() {
+        assert!(unused("let _x = 1").is_empty());
+        assert!(unused("fn f(a, b = 1, rest...) do\nend\nf(1)").is_empty());
+    }
+
+    #[test]
+    fn updating_a_variable_from_itself_is_not_a_read() {
+        assert_eq!(
+            unused("let mod count = 0\ncount++\ncount = count * 2"),
+            ["count"]
+        );
+        assert!(unused("let mod count = 0\ncount++\nprint(count)").is_empty());
+        assert!(unused("let mod a = 0\nlet mod b = 0\nb = a\na = b").is_empty());
+    }
+}
@@ -1,40 +1,205 @@
-mod ast;
-mod interpreter;
-mod lexer;
-mod parser;
-
-use interpreter::Interpreter;
-use lexer::Lexer;
-use parser::Parser;
+use blood::{BloodError, Interpreter, compile, format_source, optimize, unused_variables};
 use std::env;
 use std::fs;
+use std::io::{self, Write};
 use std::process;
+use std::time::Instant;
+
+/// Iterations after which `--warn-loops` flags a `loop` as possibly infinite.
+const LOOP_WARNING_ITERATIONS: usize = 10_000_000;
+
+const USAGE: &str = "Usage: blood [--check] [--strict] [--truthy] [--newlines] [--time] [--warn-unused] [--warn-loops] [<filename.bd>]
+       blood fmt [--newlines] <filename.bd>";
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    if env::args().nth(1).as_deref() == Some("fmt") {
+        fmt(env::args().skip(2));
+        return;
+    }
 
-    if args.len() < 2 {
-        eprintln!("Usage: blood <filename.bd>");
-        process::exit(1);
+    let mut check = false;
+    let mut newlines = false;
+    let mut strict = false;
+    let mut time = false;
+    let mut truthy = false;
+    let mut warn_unused = false;
+    let mut warn_loops = false;
+    let mut filename = None;
+
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--check" => check = true,
+            "--newlines" => newlines = true,
+            "--strict" => strict = true,
+            "--time" => time = true,
+            "--truthy" => truthy = true,
+            "--warn-unused" => warn_unused = true,
+            "--warn-loops" => warn_loops = true,
+            _ if arg.starts_with("--") => {
+                eprintln!("Unknown option '{}'", arg);
+                process::exit(1);
+            }
+            _ => filename = Some(arg),
+        }
+    }
+
+    let filename = match filename {
+        Some(f) => f,
+        None if !check => {
+            let interpreter = Interpreter::new()
+                .with_strict_returns(strict)
+                .with_truthy_conditions(truthy)
+                .with_newline_terminators(newlines)
+                .with_expression_echo(true);
+            repl(interpreter, newlines);
+            return;
+        }
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+    let code = read_source(&filename);
+
+    let parse_start = Instant::now();
+    let program = match compile(code, newlines) {
+        Ok(program) => optimize(program),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let parse_time = parse_start.elapsed();
+
+    if warn_unused {
+        for warning in unused_variables(&program) {
+            eprintln!("{}", warning);
+        }
+    }
+
+    // --check stops once the file is known to parse.
+    if check {
+        return;
+    }
+
+    let mut interpreter = Interpreter::new()
+        .with_script_path(&filename)
+        .with_strict_returns(strict)
+        .with_truthy_conditions(truthy)
+        .with_newline_terminators(newlines);
+    if warn_loops {
+        interpreter = interpreter.with_loop_warning(LOOP_WARNING_ITERATIONS);
+    }
+    let run_start = Instant::now();
+    let result = interpreter.interpret(program);
+    let run_time = run_start.elapsed();
+
+    if time {
+        eprintln!("parse:     {:?}", parse_time);
+        eprintln!("interpret: {:?}", run_time);
     }
 
-    let filename = &args[1];
-    let code = match fs::read_to_string(filename) {
+    match result {
+        Ok(()) => {}
+        Err(BloodError::Exit(code)) => process::exit(code),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn read_source(filename: &str) -> String {
+    match fs::read_to_string(filename) {
         Ok(c) => c,
         Err(e) => {
             eprintln!("Error reading file '{}': {}", filename, e);
             process::exit(1);
         }
+    }
+}
+
+/// `blood fmt`: prints the file in the canonical layout to stdout.
+fn fmt(args: impl Iterator<Item = String>) {
+    let mut newlines = false;
+    let mut filename = None;
+    for arg in args {
+        match arg.as_str() {
+            "--newlines" => newlines = true,
+            _ if arg.starts_with("--") => {
+                eprintln!("Unknown option '{}'", arg);
+                process::exit(1);
+            }
+            _ => filename = Some(arg),
+        }
+    }
+    let Some(filename) = filename else {
+        eprintln!("{}", USAGE);
+        process::exit(1);
     };
 
-    let lexer = Lexer::new(code);
-    let mut parser = Parser::new(lexer);
+    match format_source(read_source(&filename), newlines) {
+        Ok(formatted) => print!("{}", formatted),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
 
-    let program = parser.parse_program();
+/// Reads statements from stdin and runs each entry as soon as it parses,
+/// keeping globals between entries. An entry with an unclosed block or call
+/// continues on the next line. Lines starting with `:` are REPL commands.
+fn repl(mut interpreter: Interpreter, newlines: bool) {
+    let mut entry = String::new();
+    loop {
+        print!("{}", if entry.is_empty() { "> " } else { "... " });
+        io::stdout().flush().unwrap();
 
-    let mut interpreter = Interpreter::new();
-    if let Err(e) = interpreter.interpret(program) {
-        eprintln!("{}", e);
-        process::exit(1);
+        let mut line = String::new();
+        match io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => {
+                println!();
+                return;
+            }
+            Ok(_) => {}
+        }
+
+        if entry.is_empty() {
+            match line.trim() {
+                "" => continue,
+                ":quit" => return,
+                ":builtins" => {
+                    for usage in Interpreter::builtins() {
+                        println!("{}", usage);
+                    }
+                    continue;
+                }
+                command if command.starts_with(':') => {
+                    eprintln!("Unknown command '{}' (try :builtins or :quit)", command);
+                    continue;
+                }
+                _ => {}
+            }
+        }
+
+        entry.push_str(&line);
+        let program = match compile(entry.clone(), newlines) {
+            Ok(program) => optimize(program),
+            // The parser ran out of input, so the entry isn't finished yet.
+            Err(BloodError::Parse { message, .. }) if message.contains("EOF") => continue,
+            Err(e) => {
+                eprintln!("{}", e);
+                entry.clear();
+                continue;
+            }
+        };
+        entry.clear();
+
+        match interpreter.interpret(program) {
+            Ok(()) => {}
+            Err(BloodError::Exit(code)) => process::exit(code),
+            Err(e) => eprintln!("{}", e),
+        }
     }
 }
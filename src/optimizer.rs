@@ -0,0 +1,224 @@
+use crate::ast::{Expr, Op, Param, Stmt};
+
+/// Folds constant sub-expressions made only of literals into a single literal.
+///
+/// Anything that could fail at runtime (integer overflow, division by zero,
+/// type mismatches) is left untouched, so running it still produces the same
+/// runtime error as unoptimized code.
+pub fn optimize(stmts: Vec<Stmt>) -> Vec<Stmt> {
+    stmts.into_iter().map(fold_stmt).collect()
+}
+
+fn fold_stmt(stmt: Stmt) -> Stmt {
+    match stmt {
+        Stmt::Let {
+            name,
+            mutable,
+            value,
+        } => Stmt::Let {
+            name,
+            mutable,
+            value: fold_expr(value),
+        },
+        Stmt::LetMulti {
+            names,
+            mutable,
+            value,
+        } => Stmt::LetMulti {
+            names,
+            mutable,
+            value: fold_expr(value),
+        },
+        Stmt::Assign { name, value } => Stmt::Assign {
+            name,
+            value: fold_expr(value),
+        },
+        Stmt::Print(expr) => Stmt::Print(fold_expr(expr)),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => Stmt::If {
+            condition: fold_expr(condition),
+            then_branch: optimize(then_branch),
+            else_branch: else_branch.map(optimize),
+        },
+        Stmt::While {
+            condition,
+            body,
+            else_branch,
+        } => Stmt::While {
+            condition: fold_expr(condition),
+            body: optimize(body),
+            else_branch: else_branch.map(optimize),
+        },
+        Stmt::Loop { body } => Stmt::Loop {
+            body: optimize(body),
+        },
+        Stmt::Repeat { count, body } => Stmt::Repeat {
+            count: fold_expr(count),
+            body: optimize(body),
+        },
+        Stmt::DoWhile { body, condition } => Stmt::DoWhile {
+            body: optimize(body),
+            condition: fold_expr(condition),
+        },
+        Stmt::For {
+            init,
+            condition,
+            step,
+            body,
+        } => Stmt::For {
+            init: init.into_iter().map(fold_stmt).collect(),
+            condition: fold_expr(condition),
+            step: step.into_iter().map(fold_stmt).collect(),
+            body: optimize(body),
+        },
+        Stmt::Return(expr) => Stmt::Return(fold_expr(expr)),
+        Stmt::Fn {
+            name,
+            params,
+            return_type,
+            body,
+        } => Stmt::Fn {
+            name,
+            return_type,
+            params: params
+                .into_iter()
+                .map(|p| Param {
+                    default: p.default.map(fold_expr),
+                    ..p
+                })
+                .collect(),
+            body: optimize(body),
+        },
+        Stmt::ExprStmt(expr) => Stmt::ExprStmt(fold_expr(expr)),
+        Stmt::Break | Stmt::Continue | Stmt::Import(_) => stmt,
+    }
+}
+
+fn fold_expr(expr: Expr) -> Expr {
+    match expr {
+        Expr::Unary(op, right) => {
+            let right = fold_expr(*right);
+            match (&op, &right) {
+                (Op::Not, Expr::Boolean(b)) => Expr::Boolean(!b),
+                _ => Expr::Unary(op, Box::new(right)),
+            }
+        }
+        Expr::Binary(left, op, right) => {
+            let left = fold_expr(*left);
+            let right = fold_expr(*right);
+            match fold_binary(&left, &op, &right) {
+                Some(folded) => folded,
+                None => Expr::Binary(Box::new(left), op, Box::new(right)),
+            }
+        }
+        Expr::Chain(first, rest) => Expr::Chain(
+            Box::new(fold_expr(*first)),
+            rest.into_iter()
+                .map(|(op, operand)| (op, fold_expr(operand)))
+                .collect(),
+        ),
+        Expr::Array(elements) => Expr::Array(elements.into_iter().map(fold_expr).collect()),
+        Expr::Map(entries) => Expr::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| (fold_expr(key), fold_expr(value)))
+                .collect(),
+        ),
+        Expr::Call(name, args) => Expr::Call(name, args.into_iter().map(fold_expr).collect()),
+        Expr::Index(collection, index) => Expr::Index(
+            Box::new(fold_expr(*collection)),
+            Box::new(fold_expr(*index)),
+        ),
+        Expr::CallValue(callee, args) => Expr::CallValue(
+            Box::new(fold_expr(*callee)),
+            args.into_iter().map(fold_expr).collect(),
+        ),
+        // Parentheses have already shaped the tree, so they can go.
+        Expr::Grouping(inner) => fold_expr(*inner),
+        _ => expr,
+    }
+}
+
+fn fold_binary(left: &Expr, op: &Op, right: &Expr) -> Option<Expr> {
+    match (left, right) {
+        (Expr::Number(a), Expr::Number(b)) => {
+            let (a, b) = (*a, *b);
+            match op {
+                Op::Add => a.checked_add(b).map(Expr::Number),
+                Op::Sub => a.checked_sub(b).map(Expr::Number),
+                Op::Mul => a.checked_mul(b).map(Expr::Number),
+                Op::Div => a.checked_div(b).map(Expr::Number),
+                Op::Mod => a.checked_rem(b).map(Expr::Number),
+                Op::Equal => Some(Expr::Boolean(a == b)),
+                Op::NotEqual => Some(Expr::Boolean(a != b)),
+                Op::Lt => Some(Expr::Boolean(a < b)),
+                Op::Gt => Some(Expr::Boolean(a > b)),
+                Op::LtEq => Some(Expr::Boolean(a <= b)),
+                Op::GtEq => Some(Expr::Boolean(a >= b)),
+                _ => None,
+            }
+        }
+        (Expr::Boolean(a), Expr::Boolean(b)) => {
+            let (a, b) = (*a, *b);
+            match op {
+                Op::And => Some(Expr::Boolean(a && b)),
+                Op::Or => Some(Expr::Boolean(a || b)),
+                Op::Equal => Some(Expr::Boolean(a == b)),
+                Op::NotEqual => Some(Expr::Boolean(a != b)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fold(a: i64, op: Op, b: i64) -> Expr {
+        fold_expr(Expr::Binary(
+            Box::new(Expr::Number(a)),
+            op,
+            Box::new(Expr::Number(b)),
+        ))
+    }
+
+    #[test]
+    fn folds_integer_arithmetic() {
+        assert_eq!(fold(2, Op::Add, 3), Expr::Number(5));
+        assert_eq!(fold(7, Op::Mod, 4), Expr::Number(3));
+    }
+
+    #[test]
+    fn compile_leaves_folding_to_optimize() {
+        let program = crate::compile("print(2 + 3)".to_string(), false).unwrap();
+        assert_eq!(
+            program,
+            vec![Stmt::Print(Expr::Binary(
+                Box::new(Expr::Number(2)),
+                Op::Add,
+                Box::new(Expr::Number(3)),
+            ))]
+        );
+        assert_eq!(optimize(program), vec![Stmt::Print(Expr::Number(5))]);
+    }
+
+    #[test]
+    fn leaves_failing_arithmetic_for_the_interpreter() {
+        for (a, op, b) in [
+            (i64::MAX, Op::Add, 1),
+            (i64::MIN, Op::Sub, 1),
+            (i64::MAX, Op::Mul, 2),
+            (i64::MIN, Op::Div, -1),
+            (1, Op::Div, 0),
+            (1, Op::Mod, 0),
+        ] {
+            let expr = fold(a, op, b);
+            assert!(matches!(expr, Expr::Binary(..)), "{:?} was folded", expr);
+        }
+    }
+}
@@ -1,297 +1,495 @@
-use crate::ast::{Expr, Op, Stmt};
+use crate::ast::{Expr, Op, Param, Stmt, TypeTag};
+use crate::error::BloodError;
 use crate::lexer::{Lexer, Token};
 
 pub struct Parser {
     lexer: Lexer,
     current_token: Token,
+    // Line of the token consumed last.
+    previous_line: usize,
+    newline_terminators: bool,
+    // How many loops and functions enclose the current statement, so stray
+    // `break`, `continue` and `return` are rejected before anything runs.
+    loop_depth: usize,
+    function_depth: usize,
 }
 
 impl Parser {
-    pub fn new(mut lexer: Lexer) -> Self {
-        let current_token = lexer.next_token();
-        Self {
+    pub fn new(mut lexer: Lexer) -> Result<Self, BloodError> {
+        let current_token = lexer.next_token()?;
+        let newline_terminators = lexer.newline_terminators();
+        Ok(Self {
             lexer,
             current_token,
+            previous_line: 1,
+            newline_terminators,
+            loop_depth: 0,
+            function_depth: 0,
+        })
+    }
+
+    /// Formats a syntax error at the line of the current token.
+    fn error(&self, message: String) -> BloodError {
+        BloodError::Parse {
+            line: self.lexer.token_line(),
+            message,
         }
     }
 
-    fn eat(&mut self, token: Token) {
+    fn eat(&mut self, token: Token) -> Result<(), BloodError> {
         if std::mem::discriminant(&self.current_token) == std::mem::discriminant(&token) {
-            self.current_token = self.lexer.next_token();
+            self.previous_line = self.lexer.token_line();
+            self.current_token = self.lexer.next_token()?;
+            Ok(())
         } else {
-            panic!(
+            Err(self.error(format!(
                 "Expected token {:?}, but found {:?}",
                 token, self.current_token
-            );
+            )))
         }
     }
 
-    pub fn parse_program(&mut self) -> Vec<Stmt> {
+    pub fn parse_program(&mut self) -> Result<Vec<Stmt>, BloodError> {
         let mut statements = Vec::new();
         while self.current_token != Token::EOF {
-            statements.push(self.parse_statement());
+            statements.push(self.parse_statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn parse_statement(&mut self) -> Result<Stmt, BloodError> {
+        let stmt = self.parse_statement_body()?;
+        self.end_statement()?;
+        Ok(stmt)
+    }
+
+    /// With newline terminators, a statement must be followed by a line break
+    /// or by the keyword closing its block.
+    fn end_statement(&mut self) -> Result<(), BloodError> {
+        if !self.newline_terminators {
+            return Ok(());
+        }
+        match self.current_token {
+            Token::Newline => self.eat(Token::Newline),
+            Token::End | Token::Else | Token::ElseIf | Token::Until | Token::EOF => Ok(()),
+            _ => Err(self.error(format!(
+                "Expected a line break after statement, but found {:?}",
+                self.current_token
+            ))),
         }
-        statements
     }
 
-    fn parse_statement(&mut self) -> Stmt {
+    fn parse_statement_body(&mut self) -> Result<Stmt, BloodError> {
         match self.current_token {
             Token::Let => self.parse_let(),
             Token::Print => self.parse_print(),
             Token::If => self.parse_if(),
             Token::While => self.parse_while(),
             Token::Loop => self.parse_loop(),
+            Token::Repeat => self.parse_repeat(),
+            Token::For => self.parse_for(),
             Token::Break => {
-                self.eat(Token::Break);
-                Stmt::Break
+                if self.loop_depth == 0 {
+                    return Err(self.error("'break' used outside of loop".to_string()));
+                }
+                self.eat(Token::Break)?;
+                Ok(Stmt::Break)
             }
             Token::Continue => {
-                self.eat(Token::Continue);
-                Stmt::Continue
+                if self.loop_depth == 0 {
+                    return Err(self.error("'continue' used outside of loop".to_string()));
+                }
+                self.eat(Token::Continue)?;
+                Ok(Stmt::Continue)
             }
             Token::Return => self.parse_return(),
             Token::Fn => self.parse_fn(),
-            Token::Identifier(_) => self.parse_identifier_stmt(),
-            _ => panic!("Unexpected token in statement: {:?}", self.current_token),
+            Token::Import => self.parse_import(),
+            _ => self.parse_expr_stmt(),
         }
     }
 
-    fn parse_identifier_stmt(&mut self) -> Stmt {
-        let name = match &self.current_token {
-            Token::Identifier(name) => name.clone(),
-            _ => panic!("Expected identifier"),
+    /// Parses an assignment or an expression used as a statement.
+    fn parse_expr_stmt(&mut self) -> Result<Stmt, BloodError> {
+        let expr = self.parse_expr()?;
+        // `x++` and `x--` are shorthand for `x = x + 1` and `x = x - 1`.
+        let step = match self.current_token {
+            Token::PlusPlus => Some(Op::Add),
+            Token::MinusMinus => Some(Op::Sub),
+            _ => None,
         };
-        self.eat(Token::Identifier(String::new()));
-
-        if self.current_token == Token::Equal {
-            self.eat(Token::Equal);
-            let value = self.parse_expr();
-            Stmt::Assign { name, value }
-        } else if self.current_token == Token::LParen {
-            self.eat(Token::LParen);
-            let args = self.parse_arguments();
-            self.eat(Token::RParen);
-            Stmt::ExprStmt(Expr::Call(name, args))
-        } else {
-            panic!(
-                "Unexpected token after identifier in statement: {:?}",
-                self.current_token
-            );
+        if step.is_none() && self.current_token != Token::Equal {
+            return Ok(Stmt::ExprStmt(expr));
+        }
+        match expr {
+            Expr::Variable(name) => {
+                let value = match step {
+                    Some(op) => {
+                        self.eat(self.current_token.clone())?;
+                        Expr::Binary(
+                            Box::new(Expr::Variable(name.clone())),
+                            op,
+                            Box::new(Expr::Number(1)),
+                        )
+                    }
+                    None => {
+                        self.eat(Token::Equal)?;
+                        self.parse_expr()?
+                    }
+                };
+                Ok(Stmt::Assign { name, value })
+            }
+            _ => Err(self.error("Only a variable can be assigned to".to_string())),
         }
     }
 
-    fn parse_fn(&mut self) -> Stmt {
-        self.eat(Token::Fn);
+    fn parse_fn(&mut self) -> Result<Stmt, BloodError> {
+        self.eat(Token::Fn)?;
         let name = match &self.current_token {
             Token::Identifier(name) => name.clone(),
-            _ => panic!("Expected function name"),
+            _ => return Err(self.error("Expected function name".to_string())),
         };
-        self.eat(Token::Identifier(String::new()));
+        self.eat(Token::Identifier(String::new()))?;
 
-        self.eat(Token::LParen);
+        self.eat(Token::LParen)?;
         let mut params = Vec::new();
         if self.current_token != Token::RParen {
             loop {
                 let param_name = match &self.current_token {
                     Token::Identifier(name) => name.clone(),
-                    _ => panic!("Expected parameter name"),
+                    _ => return Err(self.error("Expected parameter name".to_string())),
+                };
+                self.eat(Token::Identifier(String::new()))?;
+
+                if self.current_token == Token::Ellipsis {
+                    self.eat(Token::Ellipsis)?;
+                    if self.current_token != Token::RParen {
+                        return Err(self.error(format!(
+                            "Rest parameter '{}' must be the last parameter",
+                            param_name
+                        )));
+                    }
+                    params.push(Param {
+                        name: param_name,
+                        default: None,
+                        rest: true,
+                        ty: None,
+                    });
+                    break;
+                }
+
+                let ty = self.parse_annotation()?;
+                let default = if self.current_token == Token::Equal {
+                    self.eat(Token::Equal)?;
+                    Some(self.parse_expr()?)
+                } else {
+                    None
                 };
-                self.eat(Token::Identifier(String::new()));
-                params.push(param_name);
+                if default.is_none() && params.iter().any(|p: &Param| p.default.is_some()) {
+                    return Err(self.error(format!(
+                        "Parameter '{}' without a default follows a parameter with one",
+                        param_name
+                    )));
+                }
+                params.push(Param {
+                    name: param_name,
+                    default,
+                    rest: false,
+                    ty,
+                });
 
                 if self.current_token == Token::Comma {
-                    self.eat(Token::Comma);
+                    self.eat(Token::Comma)?;
                 } else {
                     break;
                 }
             }
         }
-        self.eat(Token::RParen);
+        self.eat(Token::RParen)?;
+        let return_type = self.parse_annotation()?;
 
-        self.eat(Token::Do);
+        self.eat(Token::Do)?;
+        let enclosing_loops = std::mem::replace(&mut self.loop_depth, 0);
+        self.function_depth += 1;
         let mut body = Vec::new();
         while !self.check_end_of_block() {
-            body.push(self.parse_statement());
+            body.push(self.parse_statement()?);
         }
-        self.eat(Token::End);
+        self.function_depth -= 1;
+        self.loop_depth = enclosing_loops;
+        self.eat(Token::End)?;
 
-        Stmt::Fn { name, params, body }
+        Ok(Stmt::Fn {
+            name,
+            params,
+            return_type,
+            body,
+        })
+    }
+
+    /// Parses an optional `: type` annotation.
+    fn parse_annotation(&mut self) -> Result<Option<TypeTag>, BloodError> {
+        if self.current_token != Token::Colon {
+            return Ok(None);
+        }
+        self.eat(Token::Colon)?;
+        let name = match &self.current_token {
+            Token::Identifier(name) => name.clone(),
+            Token::Nil => "nil".to_string(),
+            _ => return Err(self.error("Expected a type name after ':'".to_string())),
+        };
+        let ty = TypeTag::from_name(&name).ok_or_else(|| {
+            self.error(format!(
+                "Unknown type '{}'; expected int, float, number, string, bool, nil, array, map or function",
+                name
+            ))
+        })?;
+        self.eat(self.current_token.clone())?;
+        Ok(Some(ty))
     }
 
-    fn parse_return(&mut self) -> Stmt {
-        self.eat(Token::Return);
+    fn parse_import(&mut self) -> Result<Stmt, BloodError> {
+        self.eat(Token::Import)?;
+        let path = match &self.current_token {
+            Token::String(path) => path.clone(),
+            _ => return Err(self.error("Expected a file path string after import".to_string())),
+        };
+        self.eat(Token::String(String::new()))?;
+        Ok(Stmt::Import(path))
+    }
 
-        if matches!(
-            self.current_token,
-            Token::End
-                | Token::Else
-                | Token::ElseIf
-                | Token::EOF
-                | Token::Let
-                | Token::Print
-                | Token::If
-                | Token::While
-                | Token::Loop
-                | Token::Break
-                | Token::Continue
-                | Token::Fn
-                | Token::Return
-        ) {
-            Stmt::Return(Expr::Nil)
+    fn parse_return(&mut self) -> Result<Stmt, BloodError> {
+        if self.function_depth == 0 {
+            return Err(self.error("'return' used outside of function".to_string()));
+        }
+        self.eat(Token::Return)?;
+
+        let bare = if self.newline_terminators {
+            matches!(
+                self.current_token,
+                Token::Newline
+                    | Token::End
+                    | Token::Else
+                    | Token::ElseIf
+                    | Token::Until
+                    | Token::EOF
+            )
         } else {
-            Stmt::Return(self.parse_expr())
+            // Without terminators, a bare `return` is recognised by the
+            // following token being unable to start an expression.
+            matches!(
+                self.current_token,
+                Token::End
+                    | Token::Else
+                    | Token::ElseIf
+                    | Token::Until
+                    | Token::EOF
+                    | Token::Let
+                    | Token::Print
+                    | Token::If
+                    | Token::While
+                    | Token::For
+                    | Token::Loop
+                    | Token::Repeat
+                    | Token::Break
+                    | Token::Continue
+                    | Token::Fn
+                    | Token::Return
+                    | Token::Import
+            )
+        };
+        if bare {
+            Ok(Stmt::Return(Expr::Nil))
+        } else {
+            Ok(Stmt::Return(self.parse_expr()?))
         }
     }
 
-    fn parse_arguments(&mut self) -> Vec<Expr> {
+    fn parse_arguments(&mut self) -> Result<Vec<Expr>, BloodError> {
         let mut args = Vec::new();
         if self.current_token != Token::RParen {
             loop {
-                args.push(self.parse_expr());
+                args.push(self.parse_expr()?);
                 if self.current_token == Token::Comma {
-                    self.eat(Token::Comma);
+                    self.eat(Token::Comma)?;
                 } else {
                     break;
                 }
             }
         }
-        args
+        Ok(args)
     }
 
-    fn parse_while(&mut self) -> Stmt {
-        self.eat(Token::While);
-        let condition = self.parse_expr();
-        self.eat(Token::Do);
+    fn parse_while(&mut self) -> Result<Stmt, BloodError> {
+        self.eat(Token::While)?;
+        let condition = self.parse_expr()?;
+        self.eat(Token::Do)?;
 
-        let mut body = Vec::new();
-        while !self.check_end_of_block() {
-            body.push(self.parse_statement());
+        let body = self.parse_loop_body()?;
+
+        let else_branch = if self.current_token == Token::Else {
+            self.eat(Token::Else)?;
+            let mut stmts = Vec::new();
+            while !self.check_end_of_block() {
+                stmts.push(self.parse_statement()?);
+            }
+            Some(stmts)
+        } else {
+            None
+        };
+        self.eat(Token::End)?;
+
+        Ok(Stmt::While {
+            condition,
+            body,
+            else_branch,
+        })
+    }
+
+    fn parse_loop(&mut self) -> Result<Stmt, BloodError> {
+        self.eat(Token::Loop)?;
+        self.eat(Token::Do)?;
+
+        let body = self.parse_loop_body()?;
+        self.eat(Token::End)?;
+
+        Ok(Stmt::Loop { body })
+    }
+
+    /// Parses `repeat n times do ... end`, or `repeat do ... until cond`.
+    fn parse_repeat(&mut self) -> Result<Stmt, BloodError> {
+        self.eat(Token::Repeat)?;
+        if self.current_token == Token::Do {
+            self.eat(Token::Do)?;
+            let body = self.parse_loop_body()?;
+            self.eat(Token::Until)?;
+            let condition = self.parse_expr()?;
+            return Ok(Stmt::DoWhile { body, condition });
         }
-        self.eat(Token::End);
+        let count = self.parse_expr()?;
+        self.eat(Token::Times)?;
+        self.eat(Token::Do)?;
+
+        let body = self.parse_loop_body()?;
+        self.eat(Token::End)?;
 
-        Stmt::While { condition, body }
+        Ok(Stmt::Repeat { count, body })
     }
 
-    fn parse_loop(&mut self) -> Stmt {
-        self.eat(Token::Loop);
-        self.eat(Token::Do);
+    /// Parses `for init; condition; step do ... end`. `init` is a list of
+    /// `let`s or expression statements, and `step` a list of expression
+    /// statements, both separated by commas.
+    fn parse_for(&mut self) -> Result<Stmt, BloodError> {
+        self.eat(Token::For)?;
+        let mut init = Vec::new();
+        loop {
+            init.push(match self.current_token {
+                Token::Let => self.parse_let()?,
+                _ => self.parse_expr_stmt()?,
+            });
+            if self.current_token != Token::Comma {
+                break;
+            }
+            self.eat(Token::Comma)?;
+        }
+        self.eat(Token::Semicolon)?;
+        let condition = self.parse_expr()?;
+        self.eat(Token::Semicolon)?;
+        let mut step = vec![self.parse_expr_stmt()?];
+        while self.current_token == Token::Comma {
+            self.eat(Token::Comma)?;
+            step.push(self.parse_expr_stmt()?);
+        }
+        self.eat(Token::Do)?;
+
+        let body = self.parse_loop_body()?;
+        self.eat(Token::End)?;
+
+        Ok(Stmt::For {
+            init,
+            condition,
+            step,
+            body,
+        })
+    }
 
+    /// Parses statements up to the end of a loop body, where `break` and
+    /// `continue` are allowed.
+    fn parse_loop_body(&mut self) -> Result<Vec<Stmt>, BloodError> {
+        self.loop_depth += 1;
         let mut body = Vec::new();
         while !self.check_end_of_block() {
-            body.push(self.parse_statement());
+            body.push(self.parse_statement()?);
         }
-        self.eat(Token::End);
+        self.loop_depth -= 1;
+        Ok(body)
+    }
 
-        Stmt::Loop { body }
+    fn parse_if(&mut self) -> Result<Stmt, BloodError> {
+        self.eat(Token::If)?;
+        self.parse_if_chain()
     }
 
-    fn parse_if(&mut self) -> Stmt {
-        self.eat(Token::If);
-        let condition = self.parse_expr();
-        self.eat(Token::Then);
+    /// Parses a condition and its branch once the `if`/`elseif` keyword has
+    /// been eaten, followed by any further branches and the closing `end`.
+    /// Each `elseif` becomes an `If` nested in the previous branch's else.
+    fn parse_if_chain(&mut self) -> Result<Stmt, BloodError> {
+        let condition = self.parse_expr()?;
+        self.eat(Token::Then)?;
 
         let mut then_branch = Vec::new();
         while !self.check_end_of_block() {
-            then_branch.push(self.parse_statement());
+            then_branch.push(self.parse_statement()?);
         }
 
-        let else_branch = if self.current_token == Token::ElseIf {
-            self.eat(Token::ElseIf);
-            let cond = self.parse_expr();
-            self.eat(Token::Then);
-            let mut branch = Vec::new();
-            while !self.check_end_of_block() {
-                branch.push(self.parse_statement());
+        let else_branch = match self.current_token {
+            Token::ElseIf => {
+                self.eat(Token::ElseIf)?;
+                Some(vec![self.parse_if_chain()?])
             }
-
-            let inner_else = if self.current_token == Token::ElseIf
-                || self.current_token == Token::Else
-            {
-                match self.current_token {
-                    Token::ElseIf => Some(vec![self.parse_recursive_elseif()]),
-                    Token::Else => {
-                        self.eat(Token::Else);
-                        let mut stmts = Vec::new();
-                        while self.current_token != Token::End && self.current_token != Token::EOF {
-                            stmts.push(self.parse_statement());
-                        }
-                        self.eat(Token::End);
-                        Some(stmts)
-                    }
-                    _ => unreachable!(),
-                }
-            } else {
-                self.eat(Token::End);
+            Token::Else => Some(self.parse_else()?),
+            _ => {
+                self.eat(Token::End)?;
                 None
-            };
-
-            Some(vec![Stmt::If {
-                condition: cond,
-                then_branch: branch,
-                else_branch: inner_else,
-            }])
-        } else if self.current_token == Token::Else {
-            self.eat(Token::Else);
-            let mut stmts = Vec::new();
-            while self.current_token != Token::End && self.current_token != Token::EOF {
-                stmts.push(self.parse_statement());
             }
-            self.eat(Token::End);
-            Some(stmts)
-        } else {
-            self.eat(Token::End);
-            None
         };
 
-        Stmt::If {
+        Ok(Stmt::If {
             condition,
             then_branch,
             else_branch,
-        }
+        })
     }
 
-    fn parse_recursive_elseif(&mut self) -> Stmt {
-        self.eat(Token::ElseIf);
-        let cond = self.parse_expr();
-        self.eat(Token::Then);
-        let mut branch = Vec::new();
-        while !self.check_end_of_block() {
-            branch.push(self.parse_statement());
+    /// Parses an `else` block up to and including its `end`. `else if` is
+    /// treated exactly like `elseif`, so the chain shares a single `end`.
+    fn parse_else(&mut self) -> Result<Vec<Stmt>, BloodError> {
+        self.eat(Token::Else)?;
+        if self.current_token == Token::If {
+            self.eat(Token::If)?;
+            return Ok(vec![self.parse_if_chain()?]);
         }
 
-        let else_branch = if self.current_token == Token::ElseIf {
-            Some(vec![self.parse_recursive_elseif()])
-        } else if self.current_token == Token::Else {
-            self.eat(Token::Else);
-            let mut stmts = Vec::new();
-            while self.current_token != Token::End && self.current_token != Token::EOF {
-                stmts.push(self.parse_statement());
-            }
-            self.eat(Token::End);
-            Some(stmts)
-        } else {
-            self.eat(Token::End);
-            None
-        };
-
-        Stmt::If {
-            condition: cond,
-            then_branch: branch,
-            else_branch,
+        let mut stmts = Vec::new();
+        while self.current_token != Token::End && self.current_token != Token::EOF {
+            stmts.push(self.parse_statement()?);
         }
+        self.eat(Token::End)?;
+        Ok(stmts)
     }
 
     fn check_end_of_block(&self) -> bool {
         self.current_token == Token::End
             || self.current_token == Token::Else
             || self.current_token == Token::ElseIf
+            || self.current_token == Token::Until
             || self.current_token == Token::EOF
     }
 
-    fn parse_let(&mut self) -> Stmt {
-        self.eat(Token::Let);
+    fn parse_let(&mut self) -> Result<Stmt, BloodError> {
+        self.eat(Token::Let)?;
         let mutable = if self.current_token == Token::Mod {
-            self.eat(Token::Mod);
+            self.eat(Token::Mod)?;
             true
         } else {
             false
@@ -299,69 +497,102 @@ impl Parser {
 
         let name = match &self.current_token {
             Token::Identifier(name) => name.clone(),
-            _ => panic!("Expected identifier after let"),
+            _ => return Err(self.error("Expected identifier after let".to_string())),
         };
-        self.eat(Token::Identifier(String::new()));
+        self.eat(Token::Identifier(String::new()))?;
+
+        if self.current_token == Token::Comma {
+            let mut names = vec![name];
+            while self.current_token == Token::Comma {
+                self.eat(Token::Comma)?;
+                let name = match &self.current_token {
+                    Token::Identifier(name) => name.clone(),
+                    _ => return Err(self.error("Expected identifier after ',' in let".to_string())),
+                };
+                self.eat(Token::Identifier(String::new()))?;
+                names.push(name);
+            }
+
+            self.eat(Token::Equal)?;
+            let value = self.parse_expr()?;
 
-        self.eat(Token::Equal);
-        let value = self.parse_expr();
+            return Ok(Stmt::LetMulti {
+                names,
+                mutable,
+                value,
+            });
+        }
+
+        self.eat(Token::Equal)?;
+        let value = self.parse_expr()?;
 
-        Stmt::Let {
+        Ok(Stmt::Let {
             name,
             mutable,
             value,
-        }
+        })
     }
 
-    fn parse_print(&mut self) -> Stmt {
-        self.eat(Token::Print);
-        self.eat(Token::LParen);
-        let expr = self.parse_expr();
-        self.eat(Token::RParen);
-        Stmt::Print(expr)
+    fn parse_print(&mut self) -> Result<Stmt, BloodError> {
+        self.eat(Token::Print)?;
+        self.eat(Token::LParen)?;
+        let expr = self.parse_expr()?;
+        self.eat(Token::RParen)?;
+        Ok(Stmt::Print(expr))
     }
 
-    fn parse_expr(&mut self) -> Expr {
+    fn parse_expr(&mut self) -> Result<Expr, BloodError> {
         self.parse_logic_or()
     }
 
-    fn parse_logic_or(&mut self) -> Expr {
-        let mut left = self.parse_logic_and();
+    fn parse_logic_or(&mut self) -> Result<Expr, BloodError> {
+        let mut left = self.parse_coalesce()?;
         while self.current_token == Token::Or {
-            self.eat(Token::Or);
-            let right = self.parse_logic_and();
+            self.eat(Token::Or)?;
+            let right = self.parse_coalesce()?;
             left = Expr::Binary(Box::new(left), Op::Or, Box::new(right));
         }
-        left
+        Ok(left)
+    }
+
+    fn parse_coalesce(&mut self) -> Result<Expr, BloodError> {
+        let mut left = self.parse_logic_and()?;
+        while self.current_token == Token::QuestionQuestion {
+            self.eat(Token::QuestionQuestion)?;
+            let right = self.parse_logic_and()?;
+            left = Expr::Binary(Box::new(left), Op::Coalesce, Box::new(right));
+        }
+        Ok(left)
     }
 
-    fn parse_logic_and(&mut self) -> Expr {
-        let mut left = self.parse_equality();
+    fn parse_logic_and(&mut self) -> Result<Expr, BloodError> {
+        let mut left = self.parse_equality()?;
         while self.current_token == Token::And {
-            self.eat(Token::And);
-            let right = self.parse_equality();
+            self.eat(Token::And)?;
+            let right = self.parse_equality()?;
             left = Expr::Binary(Box::new(left), Op::And, Box::new(right));
         }
-        left
+        Ok(left)
     }
 
-    fn parse_equality(&mut self) -> Expr {
-        let mut left = self.parse_relational();
+    fn parse_equality(&mut self) -> Result<Expr, BloodError> {
+        let mut left = self.parse_relational()?;
         while self.current_token == Token::EqualEqual || self.current_token == Token::BangEqual {
             let op = match self.current_token {
                 Token::EqualEqual => Op::Equal,
                 Token::BangEqual => Op::NotEqual,
                 _ => unreachable!(),
             };
-            self.eat(self.current_token.clone());
-            let right = self.parse_relational();
+            self.eat(self.current_token.clone())?;
+            let right = self.parse_relational()?;
             left = Expr::Binary(Box::new(left), op, Box::new(right));
         }
-        left
+        Ok(left)
     }
 
-    fn parse_relational(&mut self) -> Expr {
-        let mut left = self.parse_term();
+    fn parse_relational(&mut self) -> Result<Expr, BloodError> {
+        let first = self.parse_term()?;
+        let mut rest = Vec::new();
         while matches!(
             self.current_token,
             Token::Less | Token::LessEqual | Token::Greater | Token::GreaterEqual
@@ -373,30 +604,37 @@ impl Parser {
                 Token::GreaterEqual => Op::GtEq,
                 _ => unreachable!(),
             };
-            self.eat(self.current_token.clone());
-            let right = self.parse_term();
-            left = Expr::Binary(Box::new(left), op, Box::new(right));
+            self.eat(self.current_token.clone())?;
+            rest.push((op, self.parse_term()?));
+        }
+        // A single comparison stays a plain binary expression.
+        if rest.len() == 1 {
+            let (op, right) = rest.pop().unwrap();
+            return Ok(Expr::Binary(Box::new(first), op, Box::new(right)));
         }
-        left
+        if rest.is_empty() {
+            return Ok(first);
+        }
+        Ok(Expr::Chain(Box::new(first), rest))
     }
 
-    fn parse_term(&mut self) -> Expr {
-        let mut left = self.parse_factor();
+    fn parse_term(&mut self) -> Result<Expr, BloodError> {
+        let mut left = self.parse_factor()?;
         while self.current_token == Token::Plus || self.current_token == Token::Minus {
             let op = match self.current_token {
                 Token::Plus => Op::Add,
                 Token::Minus => Op::Sub,
                 _ => unreachable!(),
             };
-            self.eat(self.current_token.clone());
-            let right = self.parse_factor();
+            self.eat(self.current_token.clone())?;
+            let right = self.parse_factor()?;
             left = Expr::Binary(Box::new(left), op, Box::new(right));
         }
-        left
+        Ok(left)
     }
 
-    fn parse_factor(&mut self) -> Expr {
-        let mut left = self.parse_unary();
+    fn parse_factor(&mut self) -> Result<Expr, BloodError> {
+        let mut left = self.parse_unary()?;
         while self.current_token == Token::Star
             || self.current_token == Token::Slash
             || self.current_token == Token::Percent
@@ -407,60 +645,133 @@ impl Parser {
                 Token::Percent => Op::Mod,
                 _ => unreachable!(),
             };
-            self.eat(self.current_token.clone());
-            let right = self.parse_unary();
+            self.eat(self.current_token.clone())?;
+            let right = self.parse_unary()?;
             left = Expr::Binary(Box::new(left), op, Box::new(right));
         }
-        left
+        Ok(left)
     }
 
-    fn parse_unary(&mut self) -> Expr {
+    fn parse_unary(&mut self) -> Result<Expr, BloodError> {
         if self.current_token == Token::Not {
-            self.eat(Token::Not);
-            let expr = self.parse_unary();
-            Expr::Unary(Op::Not, Box::new(expr))
+            self.eat(Token::Not)?;
+            let expr = self.parse_unary()?;
+            Ok(Expr::Unary(Op::Not, Box::new(expr)))
         } else {
-            self.parse_primary()
+            self.parse_postfix()
+        }
+    }
+
+    /// A primary expression followed by any number of `[index]` and
+    /// `(arguments)` suffixes, as in `f(x)` or `handlers[0](x)`. A suffix has
+    /// to start on the line where the expression ends; on the next line, `[`
+    /// or `(` begins a new statement.
+    fn parse_postfix(&mut self) -> Result<Expr, BloodError> {
+        let mut expr = self.parse_primary()?;
+        while self.lexer.token_line() == self.previous_line {
+            match self.current_token {
+                Token::LBracket => {
+                    self.eat(Token::LBracket)?;
+                    let index = self.parse_expr()?;
+                    self.eat(Token::RBracket)?;
+                    expr = Expr::Index(Box::new(expr), Box::new(index));
+                }
+                Token::LParen => {
+                    self.eat(Token::LParen)?;
+                    let args = self.parse_arguments()?;
+                    self.eat(Token::RParen)?;
+                    expr = match expr {
+                        Expr::Variable(name) => Expr::Call(name, args),
+                        callee => Expr::CallValue(Box::new(callee), args),
+                    };
+                }
+                _ => break,
+            }
         }
+        Ok(expr)
     }
 
-    fn parse_primary(&mut self) -> Expr {
-        match self.current_token.clone() {
+    fn parse_primary(&mut self) -> Result<Expr, BloodError> {
+        let expr = match self.current_token.clone() {
             Token::Number(val) => {
-                self.eat(Token::Number(0));
+                self.eat(Token::Number(0))?;
                 Expr::Number(val)
             }
+            Token::Float(val) => {
+                self.eat(Token::Float(0.0))?;
+                Expr::Float(val)
+            }
+            Token::String(val) => {
+                self.eat(Token::String(String::new()))?;
+                Expr::String(val)
+            }
+            Token::Char(val) => {
+                self.eat(Token::Char(' '))?;
+                Expr::String(val.to_string())
+            }
             Token::True => {
-                self.eat(Token::True);
+                self.eat(Token::True)?;
                 Expr::Boolean(true)
             }
             Token::False => {
-                self.eat(Token::False);
+                self.eat(Token::False)?;
                 Expr::Boolean(false)
             }
             Token::Nil => {
-                self.eat(Token::Nil);
+                self.eat(Token::Nil)?;
                 Expr::Nil
             }
             Token::Identifier(name) => {
-                self.eat(Token::Identifier(String::new()));
-
-                if self.current_token == Token::LParen {
-                    self.eat(Token::LParen);
-                    let args = self.parse_arguments();
-                    self.eat(Token::RParen);
-                    Expr::Call(name, args)
-                } else {
-                    Expr::Variable(name)
-                }
+                self.eat(Token::Identifier(String::new()))?;
+                Expr::Variable(name)
             }
             Token::LParen => {
-                self.eat(Token::LParen);
-                let expr = self.parse_expr();
-                self.eat(Token::RParen);
-                expr
+                self.eat(Token::LParen)?;
+                let expr = self.parse_expr()?;
+                self.eat(Token::RParen)?;
+                Expr::Grouping(Box::new(expr))
             }
-            _ => panic!("Unexpected token in expression: {:?}", self.current_token),
-        }
+            Token::LBracket => {
+                self.eat(Token::LBracket)?;
+                let mut elements = Vec::new();
+                if self.current_token != Token::RBracket {
+                    loop {
+                        elements.push(self.parse_expr()?);
+                        if self.current_token == Token::Comma {
+                            self.eat(Token::Comma)?;
+                        } else {
+                            break;
+                        }
+                    }
+                }
+                self.eat(Token::RBracket)?;
+                Expr::Array(elements)
+            }
+            Token::LBrace => {
+                self.eat(Token::LBrace)?;
+                let mut entries = Vec::new();
+                if self.current_token != Token::RBrace {
+                    loop {
+                        let key = self.parse_expr()?;
+                        self.eat(Token::Colon)?;
+                        entries.push((key, self.parse_expr()?));
+                        if self.current_token == Token::Comma {
+                            self.eat(Token::Comma)?;
+                        } else {
+                            break;
+                        }
+                    }
+                }
+                self.eat(Token::RBrace)?;
+                Expr::Map(entries)
+            }
+            _ => {
+                return Err(self.error(format!(
+                    "Unexpected token in expression: {:?}",
+                    self.current_token
+                )));
+            }
+        };
+        Ok(expr)
     }
 }
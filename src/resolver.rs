@@ -0,0 +1,218 @@
+use crate::ast::{Expr, Param, Stmt};
+
+/// Rewrites reads of function-local variables into `Expr::ResolvedVariable`
+/// so the interpreter can index scope slots directly instead of searching by name.
+///
+/// Top-level code only ever defines globals, so variables outside function
+/// bodies (and any name that isn't a local) are left for name-based lookup.
+pub fn resolve(stmts: Vec<Stmt>) -> Vec<Stmt> {
+    let mut resolver = Resolver { scopes: Vec::new() };
+    resolver.resolve_block(stmts)
+}
+
+struct Resolver {
+    // Mirrors the interpreter's scopes within the current call frame. Empty
+    // while resolving top-level code.
+    scopes: Vec<Vec<String>>,
+}
+
+impl Resolver {
+    fn in_function(&self) -> bool {
+        !self.scopes.is_empty()
+    }
+
+    fn begin_scope(&mut self) {
+        if self.in_function() {
+            self.scopes.push(Vec::new());
+        }
+    }
+
+    fn end_scope(&mut self) {
+        if self.in_function() {
+            self.scopes.pop();
+        }
+    }
+
+    fn declare(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(name.to_string());
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<(usize, usize)> {
+        for (i, scope) in self.scopes.iter().enumerate().rev() {
+            if let Some(slot) = scope.iter().rposition(|n| n == name) {
+                return Some((self.scopes.len() - 1 - i, slot));
+            }
+        }
+        None
+    }
+
+    fn resolve_block(&mut self, stmts: Vec<Stmt>) -> Vec<Stmt> {
+        stmts.into_iter().map(|s| self.resolve_stmt(s)).collect()
+    }
+
+    fn resolve_scoped_block(&mut self, stmts: Vec<Stmt>) -> Vec<Stmt> {
+        self.begin_scope();
+        let stmts = self.resolve_block(stmts);
+        self.end_scope();
+        stmts
+    }
+
+    fn resolve_stmt(&mut self, stmt: Stmt) -> Stmt {
+        match stmt {
+            Stmt::Let {
+                name,
+                mutable,
+                value,
+            } => {
+                let value = self.resolve_expr(value);
+                self.declare(&name);
+                Stmt::Let {
+                    name,
+                    mutable,
+                    value,
+                }
+            }
+            Stmt::LetMulti {
+                names,
+                mutable,
+                value,
+            } => {
+                let value = self.resolve_expr(value);
+                for name in &names {
+                    self.declare(name);
+                }
+                Stmt::LetMulti {
+                    names,
+                    mutable,
+                    value,
+                }
+            }
+            Stmt::Assign { name, value } => Stmt::Assign {
+                name,
+                value: self.resolve_expr(value),
+            },
+            Stmt::Print(expr) => Stmt::Print(self.resolve_expr(expr)),
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => Stmt::If {
+                condition: self.resolve_expr(condition),
+                then_branch: self.resolve_scoped_block(then_branch),
+                else_branch: else_branch.map(|b| self.resolve_scoped_block(b)),
+            },
+            Stmt::While {
+                condition,
+                body,
+                else_branch,
+            } => Stmt::While {
+                condition: self.resolve_expr(condition),
+                body: self.resolve_scoped_block(body),
+                else_branch: else_branch.map(|b| self.resolve_scoped_block(b)),
+            },
+            Stmt::Loop { body } => Stmt::Loop {
+                body: self.resolve_scoped_block(body),
+            },
+            Stmt::Repeat { count, body } => Stmt::Repeat {
+                count: self.resolve_expr(count),
+                body: self.resolve_scoped_block(body),
+            },
+            Stmt::DoWhile { body, condition } => Stmt::DoWhile {
+                body: self.resolve_scoped_block(body),
+                condition: self.resolve_expr(condition),
+            },
+            Stmt::For {
+                init,
+                condition,
+                step,
+                body,
+            } => {
+                self.begin_scope();
+                let stmt = Stmt::For {
+                    init: init.into_iter().map(|s| self.resolve_stmt(s)).collect(),
+                    condition: self.resolve_expr(condition),
+                    step: step.into_iter().map(|s| self.resolve_stmt(s)).collect(),
+                    body: self.resolve_scoped_block(body),
+                };
+                self.end_scope();
+                stmt
+            }
+            Stmt::Return(expr) => Stmt::Return(self.resolve_expr(expr)),
+            Stmt::Fn {
+                name,
+                params,
+                return_type,
+                body,
+            } => {
+                self.declare(&name);
+                // A call starts a fresh frame holding only the parameters. Each
+                // default is evaluated once the parameters before it are bound.
+                let enclosing = std::mem::replace(&mut self.scopes, vec![Vec::new()]);
+                let params = params
+                    .into_iter()
+                    .map(|p| {
+                        let default = p.default.map(|d| self.resolve_expr(d));
+                        self.declare(&p.name);
+                        Param { default, ..p }
+                    })
+                    .collect();
+                let body = self.resolve_block(body);
+                self.scopes = enclosing;
+                Stmt::Fn {
+                    name,
+                    params,
+                    return_type,
+                    body,
+                }
+            }
+            Stmt::ExprStmt(expr) => Stmt::ExprStmt(self.resolve_expr(expr)),
+            Stmt::Break | Stmt::Continue | Stmt::Import(_) => stmt,
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: Expr) -> Expr {
+        match expr {
+            Expr::Variable(name) => match self.lookup(&name) {
+                Some((depth, slot)) => Expr::ResolvedVariable { name, depth, slot },
+                None => Expr::Variable(name),
+            },
+            Expr::Unary(op, right) => Expr::Unary(op, Box::new(self.resolve_expr(*right))),
+            Expr::Binary(left, op, right) => Expr::Binary(
+                Box::new(self.resolve_expr(*left)),
+                op,
+                Box::new(self.resolve_expr(*right)),
+            ),
+            Expr::Chain(first, rest) => Expr::Chain(
+                Box::new(self.resolve_expr(*first)),
+                rest.into_iter()
+                    .map(|(op, operand)| (op, self.resolve_expr(operand)))
+                    .collect(),
+            ),
+            Expr::Array(elements) => {
+                Expr::Array(elements.into_iter().map(|e| self.resolve_expr(e)).collect())
+            }
+            Expr::Map(entries) => Expr::Map(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (self.resolve_expr(key), self.resolve_expr(value)))
+                    .collect(),
+            ),
+            Expr::Call(name, args) => Expr::Call(
+                name,
+                args.into_iter().map(|e| self.resolve_expr(e)).collect(),
+            ),
+            Expr::Index(collection, index) => Expr::Index(
+                Box::new(self.resolve_expr(*collection)),
+                Box::new(self.resolve_expr(*index)),
+            ),
+            Expr::CallValue(callee, args) => Expr::CallValue(
+                Box::new(self.resolve_expr(*callee)),
+                args.into_iter().map(|e| self.resolve_expr(e)).collect(),
+            ),
+            Expr::Grouping(inner) => Expr::Grouping(Box::new(self.resolve_expr(*inner))),
+            _ => expr,
+        }
+    }
+}
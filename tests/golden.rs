@@ -0,0 +1,106 @@
+//! Runs every `tests/golden/*.bd` program and compares its output with the
+//! `.expected` file next to it, then checks that `blood fmt` output for each
+//! is stable and behaves the same. To add a case, drop in a new pair of files.
+
+use blood::{Interpreter, compile, format_source};
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// An output sink the test can still read after handing a clone of it to
+/// the interpreter.
+#[derive(Clone, Default)]
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs the program at `path` and returns what it printed, or the error it
+/// stopped with.
+fn run(path: &Path) -> Result<String, String> {
+    let code = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    run_source(path, code)
+}
+
+/// Runs `code` as if it were the file at `path`.
+fn run_source(path: &Path, code: String) -> Result<String, String> {
+    let capture = Capture::default();
+    let mut interpreter = Interpreter::new()
+        .with_script_path(&path.to_string_lossy())
+        .with_output(capture.clone());
+    compile(code, false)
+        .and_then(|program| interpreter.interpret(program))
+        .map_err(|e| e.to_string())?;
+    let output = capture.0.borrow();
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// The `.bd` files in `tests/golden`, sorted.
+fn programs() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut programs: Vec<_> = fs::read_dir(&dir)
+        .expect("tests/golden should exist")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "bd"))
+        .collect();
+    programs.sort();
+    assert!(!programs.is_empty(), "no programs in {}", dir.display());
+    programs
+}
+
+#[test]
+fn golden_programs() {
+    let programs = programs();
+    let mut failures = Vec::new();
+    for program in &programs {
+        let expected_path = program.with_extension("expected");
+        let expected = fs::read_to_string(&expected_path)
+            .unwrap_or_else(|_| panic!("missing {}", expected_path.display()));
+        match run(program) {
+            Ok(output) if output == expected => {}
+            Ok(output) => failures.push(format!(
+                "{} printed:\n{}\nexpected:\n{}",
+                program.display(),
+                output,
+                expected
+            )),
+            Err(error) => failures.push(format!("{} failed:\n{}", program.display(), error)),
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}
+
+/// Formatting a golden program must be stable, and the formatted program
+/// must still print the expected output.
+#[test]
+fn golden_programs_format_stably() {
+    for program in &programs() {
+        let code = fs::read_to_string(program).unwrap();
+        let once = format_source(code, false)
+            .unwrap_or_else(|e| panic!("{} failed to format: {}", program.display(), e));
+        let twice = format_source(once.clone(), false).unwrap();
+        assert_eq!(
+            twice,
+            once,
+            "formatting {} twice differs",
+            program.display()
+        );
+
+        let expected = fs::read_to_string(program.with_extension("expected")).unwrap();
+        assert_eq!(
+            run_source(program, once),
+            Ok(expected),
+            "formatted {} prints something else",
+            program.display()
+        );
+    }
+}